@@ -0,0 +1,154 @@
+//! Detection overlay
+//!
+//! Draws the detection boxes with their label, probability and estimated
+//! distance on the debug image. Text uses a built-in 5x7 bitmap font, so no
+//! font files are needed on the robot.
+
+use image::{Rgb, RgbImage};
+
+use crate::detection::Detection;
+
+const GLYPH_WIDTH: u32 = 5;
+const GLYPH_HEIGHT: u32 = 7;
+// Space between characters and around the text
+const TEXT_PADDING: u32 = 1;
+
+// Box colors, picked by class id
+const PALETTE: [[u8; 3]; 6] = [
+    [255, 64, 64],
+    [64, 255, 64],
+    [64, 160, 255],
+    [255, 224, 32],
+    [255, 64, 255],
+    [32, 255, 255],
+];
+const TEXT_COLOR: Rgb<u8> = Rgb([0, 0, 0]);
+
+// Rows of a 5x7 glyph, top to bottom - bit 4 is the leftmost pixel.
+// Letters are upper case only, text is upper cased before drawing.
+fn glyph(c: char) -> [u8; 7] {
+    match c {
+        ' ' => [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00],
+        '0' => [0x0E, 0x11, 0x13, 0x15, 0x19, 0x11, 0x0E],
+        '1' => [0x04, 0x0C, 0x04, 0x04, 0x04, 0x04, 0x0E],
+        '2' => [0x0E, 0x11, 0x01, 0x02, 0x04, 0x08, 0x1F],
+        '3' => [0x1F, 0x02, 0x04, 0x02, 0x01, 0x11, 0x0E],
+        '4' => [0x02, 0x06, 0x0A, 0x12, 0x1F, 0x02, 0x02],
+        '5' => [0x1F, 0x10, 0x1E, 0x01, 0x01, 0x11, 0x0E],
+        '6' => [0x06, 0x08, 0x10, 0x1E, 0x11, 0x11, 0x0E],
+        '7' => [0x1F, 0x01, 0x02, 0x04, 0x08, 0x08, 0x08],
+        '8' => [0x0E, 0x11, 0x11, 0x0E, 0x11, 0x11, 0x0E],
+        '9' => [0x0E, 0x11, 0x11, 0x0F, 0x01, 0x02, 0x0C],
+        'A' => [0x0E, 0x11, 0x11, 0x1F, 0x11, 0x11, 0x11],
+        'B' => [0x1E, 0x11, 0x11, 0x1E, 0x11, 0x11, 0x1E],
+        'C' => [0x0E, 0x11, 0x10, 0x10, 0x10, 0x11, 0x0E],
+        'D' => [0x1C, 0x12, 0x11, 0x11, 0x11, 0x12, 0x1C],
+        'E' => [0x1F, 0x10, 0x10, 0x1E, 0x10, 0x10, 0x1F],
+        'F' => [0x1F, 0x10, 0x10, 0x1E, 0x10, 0x10, 0x10],
+        'G' => [0x0E, 0x11, 0x10, 0x17, 0x11, 0x11, 0x0F],
+        'H' => [0x11, 0x11, 0x11, 0x1F, 0x11, 0x11, 0x11],
+        'I' => [0x0E, 0x04, 0x04, 0x04, 0x04, 0x04, 0x0E],
+        'J' => [0x07, 0x02, 0x02, 0x02, 0x02, 0x12, 0x0C],
+        'K' => [0x11, 0x12, 0x14, 0x18, 0x14, 0x12, 0x11],
+        'L' => [0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x1F],
+        'M' => [0x11, 0x1B, 0x15, 0x15, 0x11, 0x11, 0x11],
+        'N' => [0x11, 0x11, 0x19, 0x15, 0x13, 0x11, 0x11],
+        'O' => [0x0E, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0E],
+        'P' => [0x1E, 0x11, 0x11, 0x1E, 0x10, 0x10, 0x10],
+        'Q' => [0x0E, 0x11, 0x11, 0x11, 0x15, 0x12, 0x0D],
+        'R' => [0x1E, 0x11, 0x11, 0x1E, 0x14, 0x12, 0x11],
+        'S' => [0x0F, 0x10, 0x10, 0x0E, 0x01, 0x01, 0x1E],
+        'T' => [0x1F, 0x04, 0x04, 0x04, 0x04, 0x04, 0x04],
+        'U' => [0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0E],
+        'V' => [0x11, 0x11, 0x11, 0x11, 0x11, 0x0A, 0x04],
+        'W' => [0x11, 0x11, 0x11, 0x15, 0x15, 0x15, 0x0A],
+        'X' => [0x11, 0x11, 0x0A, 0x04, 0x0A, 0x11, 0x11],
+        'Y' => [0x11, 0x11, 0x11, 0x0A, 0x04, 0x04, 0x04],
+        'Z' => [0x1F, 0x01, 0x02, 0x04, 0x08, 0x10, 0x1F],
+        '.' => [0x00, 0x00, 0x00, 0x00, 0x00, 0x0C, 0x0C],
+        ':' => [0x00, 0x0C, 0x0C, 0x00, 0x0C, 0x0C, 0x00],
+        '-' => [0x00, 0x00, 0x00, 0x1F, 0x00, 0x00, 0x00],
+        '_' => [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x1F],
+        '%' => [0x18, 0x19, 0x02, 0x04, 0x08, 0x13, 0x03],
+        _ => [0x0E, 0x11, 0x01, 0x02, 0x04, 0x00, 0x04], // '?'
+    }
+}
+
+// Set a pixel - pixels outside the image are ignored
+fn put_pixel(img: &mut RgbImage, x: i64, y: i64, color: Rgb<u8>) {
+    if x >= 0 && y >= 0 && x < img.width() as i64 && y < img.height() as i64 {
+        img.put_pixel(x as u32, y as u32, color);
+    }
+}
+
+fn fill_rect(img: &mut RgbImage, x: i64, y: i64, width: u32, height: u32, color: Rgb<u8>) {
+    for v in y..y + height as i64 {
+        for u in x..x + width as i64 {
+            put_pixel(img, u, v, color);
+        }
+    }
+}
+
+// Rectangle outline drawn inwards from (x1, y1) - (x2, y2)
+fn draw_rect(img: &mut RgbImage, (x1, y1, x2, y2): (i64, i64, i64, i64), thickness: u32, color: Rgb<u8>) {
+    let width = (x2 - x1 + 1).max(1) as u32;
+    let height = (y2 - y1 + 1).max(1) as u32;
+    let thickness = thickness.min(width).min(height);
+    fill_rect(img, x1, y1, width, thickness, color);
+    fill_rect(img, x1, y2 + 1 - thickness as i64, width, thickness, color);
+    fill_rect(img, x1, y1, thickness, height, color);
+    fill_rect(img, x2 + 1 - thickness as i64, y1, thickness, height, color);
+}
+
+// Size of a text line in pixels, padding included
+fn text_size(text: &str) -> (u32, u32) {
+    let chars = text.chars().count() as u32;
+    (chars * (GLYPH_WIDTH + TEXT_PADDING) + TEXT_PADDING, GLYPH_HEIGHT + 2 * TEXT_PADDING)
+}
+
+// Draw a text line on a filled background, (x, y) is the top left corner
+fn draw_text(img: &mut RgbImage, x: i64, y: i64, text: &str, color: Rgb<u8>, background: Rgb<u8>) {
+    let (width, height) = text_size(text);
+    fill_rect(img, x, y, width, height, background);
+    for (i, c) in text.chars().flat_map(char::to_uppercase).enumerate() {
+        let left = x + (TEXT_PADDING + i as u32 * (GLYPH_WIDTH + TEXT_PADDING)) as i64;
+        let top = y + TEXT_PADDING as i64;
+        for (row, bits) in glyph(c).iter().enumerate() {
+            for col in 0..GLYPH_WIDTH {
+                if bits & (1 << (GLYPH_WIDTH - 1 - col)) != 0 {
+                    put_pixel(img, left + col as i64, top + row as i64, color);
+                }
+            }
+        }
+    }
+}
+
+// Draw the detections with their estimated distance [m] (None: no estimate). Boxes are in frame
+// pixels, `scale` maps them to the image (the debug image keeps the frame aspect ratio).
+pub fn draw_detections(img: &mut RgbImage, detections: &[(&Detection, Option<f64>)], scale: f32) {
+    // thicker boxes on the 640x360 image
+    let thickness = if img.width() >= 640 { 2 } else { 1 };
+    for (detection, dist) in detections {
+        let color = Rgb(PALETTE[detection.class_id % PALETTE.len()]);
+        let bbox = &detection.bbox;
+        let corners = (
+            (bbox.x1 * scale).round() as i64,
+            (bbox.y1 * scale).round() as i64,
+            (bbox.x2 * scale).round() as i64,
+            (bbox.y2 * scale).round() as i64,
+        );
+        draw_rect(img, corners, thickness, color);
+
+        // label above the box, inside it when the box touches the top edge,
+        // moved left when it would run off the right edge
+        let text = match dist {
+            Some(dist) => format!("{} {:.2} {:.1}m", detection.label, detection.score, dist),
+            None => format!("{} {:.2}", detection.label, detection.score),
+        };
+        let (text_width, text_height) = text_size(&text);
+        let (x1, y1, _, _) = corners;
+        let x = x1.min(img.width() as i64 - text_width as i64).max(0);
+        let y = if y1 >= text_height as i64 { y1 - text_height as i64 } else { y1.max(0) };
+        draw_text(img, x, y, &text, TEXT_COLOR, color);
+    }
+}
@@ -0,0 +1,282 @@
+//! Camera calibration
+//!
+//! Loads and saves the standard ROS camera calibration YAML (K, D, R, P),
+//! builds the matching sensor_msgs/CameraInfo and undistorts frames.
+
+use anyhow::{anyhow, Context};
+use image::{DynamicImage, Rgb, RgbImage};
+use rclrust_msg::sensor_msgs::msg::CameraInfo as CameraInfoMsg;
+use rclrust_msg::std_msgs::msg::Header;
+use serde::{Serialize, Deserialize};
+use std::convert::TryInto;
+use std::fs;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct YamlMatrix {
+    pub rows: usize,
+    pub cols: usize,
+    pub data: Vec<f64>,
+}
+
+impl YamlMatrix {
+    pub fn new(rows: usize, cols: usize, data: Vec<f64>) -> Self {
+        Self { rows, cols, data }
+    }
+
+    fn identity3() -> Self {
+        Self::new(3, 3, vec![1.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0])
+    }
+
+    fn check(&self, name: &str, rows: usize, cols: usize) -> anyhow::Result<()> {
+        if self.rows != rows || self.cols != cols || self.data.len() != rows * cols {
+            return Err(anyhow!("{} should be a {}x{} matrix", name, rows, cols));
+        }
+        Ok(())
+    }
+}
+
+// ROS camera calibration file (as written by camera_calibration / camera_info_manager)
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CameraCalibration {
+    pub image_width: u32,
+    pub image_height: u32,
+    #[serde(default)]
+    pub camera_name: String,
+    // Intrinsic matrix K
+    pub camera_matrix: YamlMatrix,
+    #[serde(default = "default_distortion_model")]
+    pub distortion_model: String,
+    // Distortion coefficients D (plumb_bob: k1, k2, p1, p2, k3)
+    pub distortion_coefficients: YamlMatrix,
+    // Rectification matrix R
+    #[serde(default = "YamlMatrix::identity3")]
+    pub rectification_matrix: YamlMatrix,
+    // Projection matrix P
+    pub projection_matrix: YamlMatrix,
+}
+
+fn default_distortion_model() -> String {
+    "plumb_bob".to_string()
+}
+
+impl CameraCalibration {
+    // Build a plumb_bob calibration from the intrinsics and distortion
+    pub fn new(camera_name: &str, image_size: (u32, u32), (fx, fy, cx, cy): (f64, f64, f64, f64), distortion: [f64; 5]) -> Self {
+        Self {
+            image_width: image_size.0,
+            image_height: image_size.1,
+            camera_name: camera_name.to_string(),
+            camera_matrix: YamlMatrix::new(3, 3, vec![fx, 0.0, cx, 0.0, fy, cy, 0.0, 0.0, 1.0]),
+            distortion_model: default_distortion_model(),
+            distortion_coefficients: YamlMatrix::new(1, 5, distortion.to_vec()),
+            rectification_matrix: YamlMatrix::identity3(),
+            projection_matrix: YamlMatrix::new(3, 4, vec![fx, 0.0, cx, 0.0, 0.0, fy, cy, 0.0, 0.0, 0.0, 1.0, 0.0]),
+        }
+    }
+
+    // Read a ROS camera calibration YAML file
+    pub fn load(path: &str) -> anyhow::Result<Self> {
+        let text = fs::read_to_string(path).with_context(|| format!("Can't read camera calibration {}", path))?;
+        let calibration: CameraCalibration = serde_yaml::from_str(&text)
+            .with_context(|| format!("Can't parse camera calibration {}", path))?;
+        calibration.camera_matrix.check("camera_matrix", 3, 3)?;
+        calibration.rectification_matrix.check("rectification_matrix", 3, 3)?;
+        calibration.projection_matrix.check("projection_matrix", 3, 4)?;
+        if calibration.distortion_model != "plumb_bob" {
+            return Err(anyhow!("Distortion model {} is not supported - use plumb_bob", calibration.distortion_model));
+        }
+        if calibration.image_width == 0 || calibration.image_height == 0 {
+            return Err(anyhow!("Camera calibration {} has no image size", path));
+        }
+        println!("Loaded camera calibration {} ({}x{})", path, calibration.image_width, calibration.image_height);
+        Ok(calibration)
+    }
+
+    pub fn save(&self, path: &str) -> anyhow::Result<()> {
+        let text = serde_yaml::to_string(self)?;
+        fs::write(path, text).with_context(|| format!("Can't write camera calibration {}", path))
+    }
+
+    // (fx, fy, cx, cy) scaled to an image size
+    pub fn intrinsics(&self, width: u32, height: u32) -> (f64, f64, f64, f64) {
+        let k = &self.camera_matrix.data;
+        let (sx, sy) = self.scale(width, height);
+        (k[0] * sx, k[4] * sy, k[2] * sx, k[5] * sy)
+    }
+
+    // Distortion coefficients k1, k2, p1, p2, k3 (missing ones are 0)
+    pub fn distortion(&self) -> [f64; 5] {
+        let mut d = [0.0; 5];
+        for (i, value) in self.distortion_coefficients.data.iter().take(5).enumerate() {
+            d[i] = *value;
+        }
+        d
+    }
+
+    // Calibration for another image size of the same camera
+    pub fn scaled(&self, width: u32, height: u32) -> Self {
+        let (sx, sy) = self.scale(width, height);
+        let mut calibration = self.clone();
+        for (i, value) in calibration.camera_matrix.data.iter_mut().enumerate().take(6) {
+            *value *= if i < 3 { sx } else { sy };
+        }
+        for (i, value) in calibration.projection_matrix.data.iter_mut().enumerate().take(8) {
+            *value *= if i < 4 { sx } else { sy };
+        }
+        calibration.image_width = width;
+        calibration.image_height = height;
+        calibration
+    }
+
+    fn scale(&self, width: u32, height: u32) -> (f64, f64) {
+        (width as f64 / self.image_width as f64, height as f64 / self.image_height as f64)
+    }
+
+    // CameraInfo for an image of the given size - the matrices are scaled from
+    // the calibration size. Undistorted images are rendered with K (see Undistorter),
+    // so they are described with no distortion, R = I and P = [K|0].
+    pub fn camera_info(&self, header: Header, width: u32, height: u32, undistorted: bool) -> CameraInfoMsg {
+        let scaled = self.scaled(width, height);
+        let (d, r, p) = if undistorted {
+            let k = &scaled.camera_matrix.data;
+            let p = vec![k[0], k[1], k[2], 0.0, k[3], k[4], k[5], 0.0, k[6], k[7], k[8], 0.0];
+            (vec![0.0; 5], YamlMatrix::identity3().data, p)
+        } else {
+            (scaled.distortion_coefficients.data, scaled.rectification_matrix.data, scaled.projection_matrix.data)
+        };
+
+        CameraInfoMsg {
+            header,
+            height,
+            width,
+            distortion_model: scaled.distortion_model,
+            d,
+            k: scaled.camera_matrix.data[..].try_into().unwrap(),
+            r: r[..].try_into().unwrap(),
+            p: p[..].try_into().unwrap(),
+            ..Default::default()
+        }
+    }
+}
+
+// Project a normalized image point through the plumb_bob distortion model
+pub fn distort_point(x: f64, y: f64, d: &[f64; 5]) -> (f64, f64) {
+    let [k1, k2, p1, p2, k3] = *d;
+    let r2 = x * x + y * y;
+    let radial = 1.0 + k1 * r2 + k2 * r2 * r2 + k3 * r2 * r2 * r2;
+    (
+        x * radial + 2.0 * p1 * x * y + p2 * (r2 + 2.0 * x * x),
+        y * radial + p1 * (r2 + 2.0 * y * y) + 2.0 * p2 * x * y,
+    )
+}
+
+// Removes lens distortion - the source pixel of every output pixel is
+// computed once per image size and reused for every frame
+pub struct Undistorter {
+    calibration: CameraCalibration,
+    size: (u32, u32),
+    // Source pixel position per output pixel (row major)
+    map: Vec<(f32, f32)>,
+}
+
+impl Undistorter {
+    pub fn new(calibration: CameraCalibration) -> Self {
+        Self { calibration, size: (0, 0), map: Vec::new() }
+    }
+
+    fn build_map(&mut self, width: u32, height: u32) {
+        let (fx, fy, cx, cy) = self.calibration.intrinsics(width, height);
+        let d = self.calibration.distortion();
+        self.map = (0..height)
+            .flat_map(|v| (0..width).map(move |u| (u, v)))
+            .map(|(u, v)| {
+                let x = (u as f64 - cx) / fx;
+                let y = (v as f64 - cy) / fy;
+                let (xd, yd) = distort_point(x, y, &d);
+                ((fx * xd + cx) as f32, (fy * yd + cy) as f32)
+            })
+            .collect();
+        self.size = (width, height);
+    }
+
+    pub fn apply(&mut self, img: &DynamicImage) -> DynamicImage {
+        let src = img.to_rgb8();
+        let (width, height) = src.dimensions();
+        if self.size != (width, height) {
+            self.build_map(width, height);
+        }
+        let map = &self.map;
+        let out = RgbImage::from_fn(width, height, |u, v| {
+            let (x, y) = map[(v * width + u) as usize];
+            sample_bilinear(&src, x, y)
+        });
+        DynamicImage::ImageRgb8(out)
+    }
+}
+
+fn sample_bilinear(img: &RgbImage, x: f32, y: f32) -> Rgb<u8> {
+    let (width, height) = img.dimensions();
+    if x < 0.0 || y < 0.0 || x > (width - 1) as f32 || y > (height - 1) as f32 {
+        return Rgb([0, 0, 0]);
+    }
+    let (x0, y0) = (x.floor() as u32, y.floor() as u32);
+    let (x1, y1) = ((x0 + 1).min(width - 1), (y0 + 1).min(height - 1));
+    let (fx, fy) = (x - x0 as f32, y - y0 as f32);
+    let (p00, p10, p01, p11) = (img.get_pixel(x0, y0), img.get_pixel(x1, y0), img.get_pixel(x0, y1), img.get_pixel(x1, y1));
+    let mut out = [0u8; 3];
+    for c in 0..3 {
+        let top = p00[c] as f32 * (1.0 - fx) + p10[c] as f32 * fx;
+        let bottom = p01[c] as f32 * (1.0 - fx) + p11[c] as f32 * fx;
+        out[c] = (top * (1.0 - fy) + bottom * fy).round() as u8;
+    }
+    Rgb(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn calibration() -> CameraCalibration {
+        CameraCalibration::new("test", (640, 480), (600.0, 590.0, 320.0, 240.0), [-0.2, 0.05, 0.001, -0.002, 0.0])
+    }
+
+    #[test]
+    fn scaled_halves_the_matrices() {
+        let scaled = calibration().scaled(320, 240);
+        assert_eq!((scaled.image_width, scaled.image_height), (320, 240));
+        assert_eq!(scaled.camera_matrix.data, vec![300.0, 0.0, 160.0, 0.0, 295.0, 120.0, 0.0, 0.0, 1.0]);
+        assert_eq!(scaled.projection_matrix.data, vec![300.0, 0.0, 160.0, 0.0, 0.0, 295.0, 120.0, 0.0, 0.0, 0.0, 1.0, 0.0]);
+        // distortion works on normalized coordinates - not scaled
+        assert_eq!(scaled.distortion(), calibration().distortion());
+    }
+
+    #[test]
+    fn camera_info_for_half_size_image() {
+        let info = calibration().camera_info(Header::default(), 320, 240, false);
+        assert_eq!((info.width, info.height), (320, 240));
+        assert_eq!(info.k, [300.0, 0.0, 160.0, 0.0, 295.0, 120.0, 0.0, 0.0, 1.0]);
+        assert_eq!(info.p[0], 300.0);
+        assert_eq!(info.p[5], 295.0);
+        assert_eq!(info.d, vec![-0.2, 0.05, 0.001, -0.002, 0.0]);
+
+        let undistorted = calibration().camera_info(Header::default(), 320, 240, true);
+        assert_eq!(undistorted.d, vec![0.0; 5]);
+    }
+
+    #[test]
+    fn undistorted_camera_info_projects_with_k() {
+        // camera_calibration writes a P that differs from [K|0]
+        let mut calibration = calibration();
+        calibration.rectification_matrix = YamlMatrix::new(3, 3, vec![1.0, 0.01, 0.0, -0.01, 1.0, 0.0, 0.0, 0.0, 1.0]);
+        calibration.projection_matrix = YamlMatrix::new(3, 4, vec![550.0, 0.0, 330.0, 0.0, 0.0, 560.0, 230.0, 0.0, 0.0, 0.0, 1.0, 0.0]);
+
+        let info = calibration.camera_info(Header::default(), 320, 240, true);
+        assert_eq!(info.r, [1.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0]);
+        assert_eq!(info.p, [300.0, 0.0, 160.0, 0.0, 0.0, 295.0, 120.0, 0.0, 0.0, 0.0, 1.0, 0.0]);
+
+        // the raw image keeps R and P of the file
+        let info = calibration.camera_info(Header::default(), 640, 480, false);
+        assert_eq!(info.r[1], 0.01);
+        assert_eq!(info.p[0], 550.0);
+    }
+}
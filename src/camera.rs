@@ -1,79 +1,586 @@
 //! Camera Modules
 //!
 
-use rscam::{Camera, Config};
+use anyhow::anyhow;
+use image::{DynamicImage, GrayImage, RgbImage};
+use rscam::{Camera, Config, IntervalInfo, ResolutionInfo};
+use serde::Deserialize;
+use std::collections::BTreeMap;
 use std::fs;
-use std::io::Write;
 use std::path::Path;
+use std::time::{Duration, Instant, SystemTime};
 
+use crate::controls;
+use crate::error::DetectError;
+use crate::frame_source::{FrameSource, RawFrame, SourceState};
+use crate::transform::Crop;
 
+// Pixel formats the pipeline can convert to an image
+const SUPPORTED_FORMATS: [&str; 5] = ["MJPG", "JPEG", "YUYV", "GREY", "RGB3"];
+
+// Devices probed when no device is configured
+const DEFAULT_DEVICES: [&str; 2] = ["/dev/video5", "/dev/video0"]; // Add more paths if necessary
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
 pub struct CameraConfig {
-    pub path: String,
+    // Device path, e.g. /dev/video0 or a /dev/v4l/by-id/ symlink (None: probe the default devices)
+    pub device: Option<String>,
+    // Capture (width, height)
+    pub resolution: (u32, u32),
+    // Frame interval in seconds as (numerator, denominator) - (1,30) is 30 fps
+    pub interval: (u32, u32),
+    // Pixel format fourcc, e.g. MJPG
+    pub format: String,
+    // Number of driver buffers
+    pub nbuffers: u32,
+    // V4L2 controls applied when the camera starts, e.g. exposure_auto: 1
+    pub controls: BTreeMap<String, i64>,
+    // Optional debug snapshot - every frame used for detection is also written to this file
+    pub snapshot: Option<String>,
+    // Topic namespace of this camera, e.g. front (empty: node namespace)
+    pub namespace: String,
+    // frame_id stamped on the published messages
+    pub frame_id: String,
+    // Debug image mode: none, low, med or high (None: the node --mode)
+    pub mode: Option<String>,
+    // ROS camera calibration YAML published on camera_info
+    pub calibration: Option<String>,
+    // Remove the lens distortion before detection (needs a calibration)
+    pub undistort: bool,
+    // Clockwise frame rotation in degrees: 0, 90, 180 or 270
+    pub rotate: u32,
+    // Mirror the frame left-right / upside-down (after the rotation)
+    pub hflip: bool,
+    pub vflip: bool,
+    // Part of the rotated and flipped frame that is kept
+    pub crop: Option<Crop>,
+}
+
+impl Default for CameraConfig {
+    fn default() -> Self {
+        Self {
+            device: None,
+            resolution: PREFERRED_RESOLUTION,
+            interval: PREFERRED_INTERVAL,
+            format: "MJPG".to_string(),
+            nbuffers: 1,
+            controls: BTreeMap::new(),
+            snapshot: None,
+            namespace: String::new(),
+            frame_id: "camera".to_string(),
+            mode: None,
+            calibration: None,
+            undistort: false,
+            rotate: 0,
+            hflip: false,
+            vflip: false,
+            crop: None,
+        }
+    }
 }
 
+// Consecutive capture failures before the device is considered disconnected
+const MAX_CAPTURE_FAILURES: u32 = 3;
+// Reconnect back off - doubled after every failed attempt up to the maximum
+const MIN_RECONNECT_DELAY: Duration = Duration::from_millis(500);
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(10);
 
 pub struct UsbCamera {
-    //Camera instance
-    camera: Camera, 
+    //Camera instance - None while disconnected
+    camera: Option<Camera>, 
     //Camera configuration
     config: CameraConfig,
+    //Connection state reported to the node
+    state: SourceState,
+    //Consecutive capture failures
+    failures: u32,
+    //Next reconnect attempt and the back off before the one after it
+    next_reconnect: Instant,
+    reconnect_delay: Duration,
+    //Capture time of the last frame (driver buffer timestamp)
+    captured_at: Option<SystemTime>,
 }
 
-fn open_camera() -> Result<Camera, String> {
-    let device_paths = ["/dev/video5", "/dev/video0"]; // Add more paths if necessary
+// Convert a V4L2 buffer timestamp (CLOCK_MONOTONIC, microseconds) to wall clock
+// time using the current offset between the two clocks. None for drivers that
+// don't stamp their buffers.
+fn monotonic_to_system(timestamp_us: u64) -> Option<SystemTime> {
+    if timestamp_us == 0 {
+        return None;
+    }
+    let clock_ns = |clock: libc::clockid_t| {
+        let mut ts = libc::timespec { tv_sec: 0, tv_nsec: 0 };
+        // SAFETY: clock_gettime only writes the timespec passed to it
+        unsafe { libc::clock_gettime(clock, &mut ts) };
+        ts.tv_sec as i128 * 1_000_000_000 + ts.tv_nsec as i128
+    };
+    let monotonic_now = clock_ns(libc::CLOCK_MONOTONIC);
+    let realtime_now = clock_ns(libc::CLOCK_REALTIME);
+    let captured_ns = realtime_now - (monotonic_now - timestamp_us as i128 * 1000);
+    if captured_ns <= 0 {
+        return None;
+    }
+    Some(SystemTime::UNIX_EPOCH + Duration::from_nanos(captured_ns as u64))
+}
+
+// List the /dev/video* devices
+pub fn video_devices() -> Vec<String> {
+    let mut devices: Vec<String> = match fs::read_dir("/dev") {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path().to_string_lossy().to_string())
+            .filter(|path| path.starts_with("/dev/video"))
+            .collect(),
+        Err(_) => Vec::new(),
+    };
+    devices.sort();
+    devices
+}
+
+// Open the configured device or the first default (then any /dev/video*) device that opens
+fn open_camera(device: Option<&str>) -> Result<(Camera, String), String> {
+    if let Some(path) = device {
+        return Camera::new(path)
+            .map(|camera| (camera, path.to_string()))
+            .map_err(|e| format!("Unable to open camera {}: {}", path, e));
+    }
+
+    // The device list is probed again on every call - a reconnected camera may get a new number
+    let mut device_paths: Vec<String> = DEFAULT_DEVICES.iter().map(|path| path.to_string()).collect();
+    device_paths.extend(video_devices().into_iter().filter(|path| !DEFAULT_DEVICES.contains(&path.as_str())));
 
     for path in &device_paths {
         if Path::new(path).exists() {
             match Camera::new(path) {
-                Ok(camera) => return Ok(camera),
+                Ok(camera) => return Ok((camera, path.to_string())),
                 Err(_) => continue,
             }
         }
     }
 
-    Err("Unable to open camera on any of the provided device paths".to_string())
+    Err(format!("Unable to open camera on any of the provided device paths: {}", device_paths.join(", ")))
+}
+
+// Open, validate and start a camera with the given configuration
+fn start_camera(config: &CameraConfig) -> anyhow::Result<Camera> {
+    // For Rockpi camera registered as video0, for asus - USB Camera is registered as video5
+    // - both are probed unless a device is configured
+    let (mut camera, path) = open_camera(config.device.as_deref()).map_err(|e| anyhow!("Can't open the camera: {}", e))?;
+
+    if let Err(e) = validate_mode(&camera, config) {
+        return Err(anyhow!("{} on {}\nSupported modes:\n{}", e, path, list_modes(&camera)));
+    }
+
+    // start the camera
+    camera.start(&Config {
+      interval: config.interval,
+      resolution: config.resolution,
+      format: config.format.as_bytes(),
+      nbuffers: config.nbuffers,
+      ..Default::default()
+    })
+    .map_err(|e| anyhow!("Can't start camera capture: {}", e))?;
+    println!("Camera {} started: {} {}x{} @ {}/{} s", path, config.format,
+        config.resolution.0, config.resolution.1, config.interval.0, config.interval.1);
+
+    for (name, value) in &config.controls {
+        apply_control(&camera, name, *value)?;
+    }
+
+    Ok(camera)
 }
 
 impl UsbCamera {
     //set the camera 
-    pub fn new() -> Self {
-        // For Rockpi camera registered as video0
-       //let mut camera = Camera::new("/dev/video0").expect("Can't open the camera ");
-       // For asus - USB Camera is registered as video5  
-       //let mut camera = Camera::new("/dev/video5").expect("Can't open the camera ");
-       let mut camera = open_camera().expect("Can't open the camera");
-
-        // start the camera
-        camera.start(&Config {
-          interval: (1,30),
-          resolution: (640,360),
-          format: b"MJPG",
-          nbuffers: 1,
-          ..Default::default()
+    pub fn new(config: CameraConfig) -> anyhow::Result<Self> {
+        let camera = start_camera(&config)?;
+
+        Ok(Self{
+            camera: Some(camera),
+            config,
+            state: SourceState::Connected,
+            failures: 0,
+            next_reconnect: Instant::now(),
+            reconnect_delay: MIN_RECONNECT_DELAY,
+            captured_at: None,
+        })
+    }
+    // Capture a frame and return the raw frame bytes in the configured pixel format
+    // and the driver capture time
+    // (frames are dequeued continuously by the capture thread - no stale buffers to flush)
+    pub fn take_pic(&self) -> Result<(Vec<u8>, Option<SystemTime>), Box<dyn std::error::Error>> {
+        let camera = self.camera.as_ref().ok_or("Camera disconnected")?;
+        let frame = camera.capture()?; // get picture
+
+        Ok((frame.to_vec(), monotonic_to_system(frame.get_timestamp())))
+    }
+
+    // Set a camera control - kept in the configuration so it survives a reconnect
+    pub fn set_control(&mut self, name: &str, value: i64) -> anyhow::Result<()> {
+        if let Some(camera) = &self.camera {
+            apply_control(camera, name, value)?;
+        }
+        self.config.controls.insert(name.to_string(), value);
+        Ok(())
+    }
+
+    // Close the device after repeated capture failures
+    fn disconnect(&mut self) {
+        eprintln!("Camera disconnected after {} failed captures", self.failures);
+        self.camera = None; // dropping the camera stops streaming and closes the device
+        self.state = SourceState::Disconnected;
+        self.next_reconnect = Instant::now() + self.reconnect_delay;
+    }
+
+    // Reopen the device once the back off expired - the state stays Disconnected
+    // until then. The first call after the back off only switches to Reconnecting,
+    // so that state is published while the device is opened by the next call.
+    fn reconnect(&mut self) -> anyhow::Result<()> {
+        let now = Instant::now();
+        if now < self.next_reconnect {
+            return Err(anyhow!("Camera disconnected - next reconnect attempt in {:?}", self.next_reconnect - now));
+        }
+        if self.state != SourceState::Reconnecting {
+            self.state = SourceState::Reconnecting;
+            return Err(anyhow!("Camera disconnected - reconnecting"));
+        }
+        match start_camera(&self.config) {
+            Ok(camera) => {
+                println!("Camera reconnected");
+                self.camera = Some(camera);
+                self.state = SourceState::Connected;
+                self.failures = 0;
+                self.reconnect_delay = MIN_RECONNECT_DELAY;
+                Ok(())
+            }
+            Err(e) => {
+                self.state = SourceState::Disconnected;
+                self.reconnect_delay = (self.reconnect_delay * 2).min(MAX_RECONNECT_DELAY);
+                self.next_reconnect = Instant::now() + self.reconnect_delay;
+                Err(anyhow!("Camera reconnect failed (next attempt in {:?}): {}", self.reconnect_delay, e))
+            }
+        }
+    }
+
+}
+
+impl FrameSource for UsbCamera {
+    fn next_frame(&mut self) -> anyhow::Result<RawFrame> {
+        if self.camera.is_none() {
+            self.reconnect()?;
+        }
+        let image_data = match self.take_pic() {
+            Ok((data, captured_at)) => {
+                self.failures = 0;
+                self.captured_at = captured_at;
+                data
+            }
+            Err(e) => {
+                self.failures += 1;
+                if self.failures >= MAX_CAPTURE_FAILURES {
+                    self.disconnect();
+                }
+                return Err(anyhow!("{}", e));
+            }
+        };
+        Ok(RawFrame::Buffer {
+            data: image_data,
+            format: self.config.format.clone(),
+            resolution: self.config.resolution,
+            snapshot: self.config.snapshot.clone(),
         })
-        .expect("can't start camera capture");
+    }
+
+    fn state(&self) -> SourceState {
+        self.state
+    }
+
+    fn capture_time(&self) -> Option<SystemTime> {
+        self.captured_at
+    }
+
+    fn controls(&self) -> Vec<(String, i64)> {
+        let camera = match &self.camera {
+            Some(camera) => camera,
+            None => return Vec::new(),
+        };
+        controls::CONTROLS.iter()
+            .filter_map(|control| controls::control_value(camera, control.id).map(|value| (control.name.to_string(), value)))
+            .collect()
+    }
+
+    fn set_control(&mut self, name: &str, value: i64) -> anyhow::Result<()> {
+        UsbCamera::set_control(self, name, value)
+    }
+}
+
+// Set a camera control by name
+fn apply_control(camera: &Camera, name: &str, value: i64) -> anyhow::Result<()> {
+    let control = controls::control_by_name(name).ok_or_else(|| anyhow!("Unknown camera control '{}' - known controls: {}", name,
+        controls::CONTROLS.iter().map(|control| control.name).collect::<Vec<_>>().join(", ")))?;
+    camera.set_control(control.id, &value)
+        .map_err(|e| anyhow!("Can't set camera control {} to {}: {}", name, value, e))?;
+    println!("Camera control {} = {}", name, value);
+    Ok(())
+}
+
+// JPEG frames - kept as captured in the debug snapshot
+pub fn is_compressed(format: &str) -> bool {
+    matches!(format, "MJPG" | "JPEG")
+}
+
+// Convert a captured frame to an RGB image - JPEG frames are decoded,
+// uncompressed frames are converted directly
+pub fn frame_to_image(data: &[u8], format: &str, resolution: (u32, u32)) -> anyhow::Result<DynamicImage> {
+    let (width, height) = resolution;
+    let img = match format {
+        "MJPG" | "JPEG" => return Ok(image::load_from_memory(data).map_err(DetectError::from)?),
+        "YUYV" => {
+            let stride = frame_stride(data, width * 2, height)?;
+            yuyv_to_rgb(data, width, height, stride)
+        }
+        "GREY" => {
+            let stride = frame_stride(data, width, height)?;
+            let gray = GrayImage::from_fn(width, height, |x, y| image::Luma([data[(y * stride + x) as usize]]));
+            DynamicImage::ImageLuma8(gray).to_rgb8()
+        }
+        "RGB3" => {
+            let stride = frame_stride(data, width * 3, height)?;
+            RgbImage::from_fn(width, height, |x, y| {
+                let i = (y * stride + x * 3) as usize;
+                image::Rgb([data[i], data[i + 1], data[i + 2]])
+            })
+        }
+        _ => return Err(anyhow!("Pixel format {} is not supported - use one of {}", format, SUPPORTED_FORMATS.join(", "))),
+    };
+    Ok(DynamicImage::ImageRgb8(img))
+}
+
+// Bytes per line of an uncompressed frame - drivers may pad lines
+fn frame_stride(data: &[u8], line_bytes: u32, height: u32) -> anyhow::Result<u32> {
+    let stride = (data.len() as u32).checked_div(height).unwrap_or(0);
+    if stride < line_bytes {
+        return Err(anyhow!("Frame too short: {} bytes for {} lines of {} bytes", data.len(), height, line_bytes));
+    }
+    Ok(stride)
+}
+
+// YUYV 4:2:2 (Y0 U Y1 V per two pixels) to RGB using BT.601 coefficients
+fn yuyv_to_rgb(data: &[u8], width: u32, height: u32, stride: u32) -> RgbImage {
+    let mut img = RgbImage::new(width, height);
+    for y in 0..height {
+        let line = &data[(y * stride) as usize..];
+        for x in 0..width {
+            let pair = ((x / 2) * 4) as usize;
+            let luma = line[pair + if x % 2 == 0 { 0 } else { 2 }] as f32;
+            let u = line[pair + 1] as f32 - 128.0;
+            let v = line[pair + 3] as f32 - 128.0;
+            let r = luma + 1.402 * v;
+            let g = luma - 0.344_136 * u - 0.714_136 * v;
+            let b = luma + 1.772 * u;
+            img.put_pixel(x, y, image::Rgb([clamp_u8(r), clamp_u8(g), clamp_u8(b)]));
+        }
+    }
+    img
+}
+
+fn clamp_u8(value: f32) -> u8 {
+    value.round().clamp(0.0, 255.0) as u8
+}
+
+// Print the controls and modes of the camera device
+pub fn print_controls(device: Option<&str>) -> anyhow::Result<()> {
+    let (camera, path) = open_camera(device).map_err(|e| anyhow!("Can't open the camera: {}", e))?;
+    println!("Camera {} controls:\n{}", path, controls::list_controls(&camera));
+    println!("Camera {} modes:\n{}", path, list_modes(&camera));
+    Ok(())
+}
+
+// Parse a "WIDTHxHEIGHT" resolution
+pub fn parse_resolution(value: &str) -> Result<(u32, u32), String> {
+    let (width, height) = value.split_once('x')
+        .ok_or_else(|| format!("Resolution should be WIDTHxHEIGHT - got '{}'", value))?;
+    match (width.trim().parse::<u32>(), height.trim().parse::<u32>()) {
+        (Ok(width), Ok(height)) if width > 0 && height > 0 => Ok((width, height)),
+        _ => Err(format!("Resolution should be WIDTHxHEIGHT - got '{}'", value)),
+    }
+}
+
+// Read a camera configuration YAML file - missing fields keep their defaults.
+// The file holds either one camera or a `cameras` list.
+pub fn load_configs(path: &str) -> anyhow::Result<Vec<CameraConfig>> {
+    let text = fs::read_to_string(path).map_err(|e| anyhow!("Can't read camera config {}: {}", path, e))?;
+    let configs = parse_configs(&text).map_err(|e| anyhow!("Can't parse camera config {}: {}", path, e))?;
+    validate_configs(&configs).map_err(|e| anyhow!("Camera config {}: {}", path, e))?;
+    Ok(configs)
+}
+
+// A camera config file holds a list of cameras under `cameras`, or a single camera
+fn parse_configs(text: &str) -> Result<Vec<CameraConfig>, serde_yaml::Error> {
+    let value: serde_yaml::Value = serde_yaml::from_str(text)?;
+    match value.get("cameras") {
+        // parsed on its own, so errors in the list are reported instead of falling back to a single camera
+        Some(cameras) => serde_yaml::from_value(cameras.clone()),
+        None => Ok(vec![serde_yaml::from_value(value)?]),
+    }
+}
+
+// Cameras must publish to distinct namespaces
+fn validate_configs(configs: &[CameraConfig]) -> anyhow::Result<()> {
+    if configs.is_empty() {
+        return Err(anyhow!("no cameras configured"));
+    }
+    for (i, config) in configs.iter().enumerate() {
+        if let Some(mode) = &config.mode {
+            if !["none", "low", "med", "high"].contains(&mode.as_str()) {
+                return Err(anyhow!("mode should be none, low, med or high - got '{}'", mode));
+            }
+        }
+        if config.undistort && config.calibration.is_none() {
+            return Err(anyhow!("undistort needs a calibration file"));
+        }
+        if ![0, 90, 180, 270].contains(&config.rotate) {
+            return Err(anyhow!("rotate should be 0, 90, 180 or 270 - got {}", config.rotate));
+        }
+        if configs[..i].iter().any(|other| other.namespace == config.namespace) {
+            return Err(anyhow!("every camera needs its own namespace - '{}' is used twice", config.namespace));
+        }
+    }
+    Ok(())
+}
+
+// Check the configured format, resolution and interval against the modes the device advertises
+fn validate_mode(camera: &Camera, config: &CameraConfig) -> Result<(), String> {
+    let format = config.format.as_bytes();
+    if format.len() != 4 {
+        return Err(format!("Pixel format '{}' should be a 4 character code", config.format));
+    }
+    if !SUPPORTED_FORMATS.contains(&config.format.as_str()) {
+        return Err(format!("Pixel format {} can't be processed - use one of {}", config.format, SUPPORTED_FORMATS.join(", ")));
+    }
+    if !camera.formats().filter_map(|info| info.ok()).any(|info| &info.format[..] == format) {
+        return Err(format!("Pixel format {} is not supported", config.format));
+    }
 
-        let config = CameraConfig{path: String::from("image.jpg")};
+    let resolutions = camera.resolutions(format).map_err(|e| format!("Can't read resolutions: {}", e))?;
+    if !resolution_supported(&resolutions, config.resolution) {
+        return Err(format!("Resolution {}x{} is not supported in {}", config.resolution.0, config.resolution.1, config.format));
+    }
 
-        Self{ camera , config}
+    let intervals = camera.intervals(format, config.resolution).map_err(|e| format!("Can't read frame intervals: {}", e))?;
+    if !interval_supported(&intervals, config.interval) {
+        return Err(format!("Frame interval {}/{} s is not supported in {} {}x{}", config.interval.0, config.interval.1,
+            config.format, config.resolution.0, config.resolution.1));
     }
-    // Capture a frame and save it to a File
-    pub fn take_pic(&self) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
-        for _ in 0..3 {
-            let _ = self.camera.capture(); // Grab a frame to reduce delay.
+    Ok(())
+}
+
+fn resolution_supported(resolutions: &ResolutionInfo, resolution: (u32, u32)) -> bool {
+    match resolutions {
+        ResolutionInfo::Discretes(sizes) => sizes.contains(&resolution),
+        ResolutionInfo::Stepwise { min, max, step } => {
+            in_steps(resolution.0, min.0, max.0, step.0) && in_steps(resolution.1, min.1, max.1, step.1)
+        }
+    }
+}
+
+fn in_steps(value: u32, min: u32, max: u32, step: u32) -> bool {
+    value >= min && value <= max && (step == 0 || (value - min).is_multiple_of(step))
+}
+
+fn interval_supported(intervals: &IntervalInfo, interval: (u32, u32)) -> bool {
+    let seconds = |(num, den): (u32, u32)| num as f64 / den.max(1) as f64;
+    match intervals {
+        // Compare the fractions num/den by cross multiplication
+        IntervalInfo::Discretes(values) => values.iter()
+            .any(|value| value.0 as u64 * interval.1 as u64 == value.1 as u64 * interval.0 as u64),
+        IntervalInfo::Stepwise { min, max, .. } => {
+            seconds(interval) >= seconds(*min) && seconds(interval) <= seconds(*max)
         }
-        let frame = self.camera.capture()?; // get picture
+    }
+}
 
-        // Save the original image to the specified file path.
-        let mut file = fs::File::create(self.config.path.clone())?;
-        file.write_all(&frame[..])?;
-        // See if I can convert vector back to image
-        //let frame_vector = frame.to_vec();
-        //let mut file = fs::File::create("test_image.jpg")?;
-        //file.write_all(&frame_vector)?;
+// Preferred capture resolution and frame interval (the defaults)
+const PREFERRED_RESOLUTION: (u32, u32) = (640, 360);
+const PREFERRED_INTERVAL: (u32, u32) = (1, 30);
+
+// Pick a mode the pipeline can use: the first supported format in SUPPORTED_FORMATS
+// order at 640x360 (or the closest width) and 30 fps (or the fastest rate)
+// Returns the default configuration with the chosen format, resolution and interval
+pub fn suggest_mode(camera: &Camera) -> Option<CameraConfig> {
+    let device_formats: Vec<[u8; 4]> = camera.formats().filter_map(|info| info.ok()).map(|info| info.format).collect();
+    let format = SUPPORTED_FORMATS.iter().find(|format| device_formats.iter().any(|f| &f[..] == format.as_bytes()))?;
+    let resolution = match camera.resolutions(format.as_bytes()).ok()? {
+        resolutions if resolution_supported(&resolutions, PREFERRED_RESOLUTION) => PREFERRED_RESOLUTION,
+        ResolutionInfo::Discretes(sizes) => *sizes.iter()
+            .min_by_key(|size| ((size.0 as i64 - PREFERRED_RESOLUTION.0 as i64).abs(), size.0 * size.1))?,
+        ResolutionInfo::Stepwise { max, .. } => max,
+    };
+    let interval = match camera.intervals(format.as_bytes(), resolution).ok()? {
+        intervals if interval_supported(&intervals, PREFERRED_INTERVAL) => PREFERRED_INTERVAL,
+        IntervalInfo::Discretes(values) => *values.iter()
+            .min_by(|a, b| (a.0 as f64 / a.1.max(1) as f64).total_cmp(&(b.0 as f64 / b.1.max(1) as f64)))?,
+        IntervalInfo::Stepwise { min, .. } => min,
+    };
+    Some(CameraConfig { format: format.to_string(), resolution, interval, ..Default::default() })
+}
 
-        Ok(frame.to_vec())
+// Human readable list of the formats, resolutions and frame rates a device supports
+pub fn list_modes(camera: &Camera) -> String {
+    let mut modes = String::new();
+    for info in camera.formats().filter_map(|info| info.ok()) {
+        let fourcc = String::from_utf8_lossy(&info.format).to_string();
+        modes.push_str(&format!("  {} ({})\n", fourcc, info.description));
+        match camera.resolutions(&info.format) {
+            Ok(ResolutionInfo::Discretes(sizes)) => {
+                for size in sizes {
+                    let rates = match camera.intervals(&info.format, size) {
+                        Ok(intervals) => format_intervals(&intervals),
+                        Err(_) => "?".to_string(),
+                    };
+                    modes.push_str(&format!("    {}x{} @ {}\n", size.0, size.1, rates));
+                }
+            }
+            Ok(ResolutionInfo::Stepwise { min, max, step }) => {
+                modes.push_str(&format!("    {}x{} - {}x{} step {}x{}\n", min.0, min.1, max.0, max.1, step.0, step.1));
+            }
+            Err(e) => modes.push_str(&format!("    can't read resolutions: {}\n", e)),
+        }
     }
+    modes
+}
 
+fn format_intervals(intervals: &IntervalInfo) -> String {
+    let fps = |(num, den): (u32, u32)| den as f64 / num.max(1) as f64;
+    match intervals {
+        IntervalInfo::Discretes(values) => values.iter()
+            .map(|value| format!("{} fps", fps(*value)))
+            .collect::<Vec<_>>()
+            .join(", "),
+        IntervalInfo::Stepwise { min, max, .. } => format!("{} - {} fps", fps(*max), fps(*min)),
+    }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_single_camera_and_camera_list() {
+        let single = parse_configs("device: /dev/video0\nformat: YUYV\n").unwrap();
+        assert_eq!(single.len(), 1);
+        assert_eq!(single[0].format, "YUYV");
+
+        let list = parse_configs("cameras:\n  - namespace: front\n  - namespace: rear\n").unwrap();
+        assert_eq!(list.iter().map(|config| config.namespace.as_str()).collect::<Vec<_>>(), ["front", "rear"]);
+    }
+
+    #[test]
+    fn reports_errors_in_camera_list() {
+        // a typo in the list must not fall back to a default single camera
+        assert!(parse_configs("cameras:\n  - namespace: front\n    fromat: YUYV\n").is_err());
+        assert!(parse_configs("cameras:\n  - namespace: front\n    nbuffers: many\n").is_err());
+        assert!(parse_configs("device: /dev/video0\nrotation: 90\n").is_err());
+    }
+}
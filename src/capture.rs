@@ -0,0 +1,194 @@
+//! Background capture
+//!
+//! A dedicated thread keeps dequeuing frames from the frame source into a
+//! "latest frame" slot, so the detection timer always gets the newest frame
+//! without waiting for (or flushing) stale camera buffers. A caller faster than
+//! the source gets the same frame again.
+
+use anyhow::anyhow;
+use image::DynamicImage;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use crate::frame_source::{FrameSource, RawFrame, SourceState};
+
+// Pause after a failed capture before retrying
+const RETRY_DELAY: Duration = Duration::from_millis(100);
+
+// Control change (name, value)
+type ControlChange = (String, i64);
+
+// Captured frame with its capture time
+#[derive(Clone)]
+pub struct Frame {
+    pub image: DynamicImage,
+    // Device capture time when the source reports it, otherwise the arrival time
+    pub captured_at: SystemTime,
+    // Frame sequence number - counts every captured frame
+    pub seq: u64,
+    // Height of the captured image - rotation and crop don't change the pixel scale
+    // of the detection boxes, so distance estimation uses this height
+    pub capture_height: u32,
+}
+
+struct Slot {
+    // Newest frame not decoded yet with its capture time and sequence number
+    frame: Option<(RawFrame, SystemTime, u64)>,
+    // Sequence number of the newest captured frame
+    seq: u64,
+    // Error of the last capture attempt (cleared by a successful capture)
+    error: Option<String>,
+    // Source connection state after the last capture attempt
+    state: SourceState,
+}
+
+pub struct CaptureThread {
+    slot: Arc<Mutex<Slot>>,
+    // Signalled after every capture attempt
+    captured: Arc<Condvar>,
+    // Newest decoded frame - handed out until a newer frame arrives
+    last: Mutex<Option<Frame>>,
+    // Control values of the source when capture started
+    controls: Vec<(String, i64)>,
+    // Control changes applied by the capture thread between frames
+    control_tx: Mutex<Sender<ControlChange>>,
+}
+
+impl CaptureThread {
+    // Start capturing from the source in the background
+    pub fn spawn(mut source: Box<dyn FrameSource>) -> anyhow::Result<Self> {
+        let slot = Arc::new(Mutex::new(Slot { frame: None, seq: 0, error: None, state: source.state() }));
+        let thread_slot = Arc::clone(&slot);
+        let captured = Arc::new(Condvar::new());
+        let thread_captured = Arc::clone(&captured);
+        let controls = source.controls();
+        let (control_tx, control_rx): (Sender<ControlChange>, Receiver<ControlChange>) = mpsc::channel();
+        thread::Builder::new()
+            .name("capture".to_string())
+            .spawn(move || {
+                let mut seq: u64 = 0;
+                loop {
+                    for (name, value) in control_rx.try_iter() {
+                        if let Err(e) = source.set_control(&name, value) {
+                            eprintln!("{}", e);
+                        }
+                    }
+                    match source.next_frame() {
+                        Ok(raw) => {
+                            seq += 1;
+                            let captured_at = source.capture_time().unwrap_or_else(SystemTime::now);
+                            let mut slot = thread_slot.lock().unwrap();
+                            slot.frame = Some((raw, captured_at, seq));
+                            slot.seq = seq;
+                            slot.error = None;
+                            slot.state = source.state();
+                            thread_captured.notify_all();
+                        }
+                        Err(e) => {
+                            {
+                                let mut slot = thread_slot.lock().unwrap();
+                                slot.error = Some(e.to_string());
+                                slot.state = source.state();
+                                thread_captured.notify_all();
+                            }
+                            thread::sleep(RETRY_DELAY);
+                        }
+                    }
+                }
+            })?;
+        Ok(Self { slot, captured, last: Mutex::new(None), controls, control_tx: Mutex::new(control_tx) })
+    }
+
+    // Connection state of the source
+    pub fn state(&self) -> SourceState {
+        self.slot.lock().unwrap().state
+    }
+
+    // Control values of the source when capture started
+    pub fn controls(&self) -> &[(String, i64)] {
+        &self.controls
+    }
+
+    // Change a source control - applied before the next capture
+    pub fn set_control(&self, name: &str, value: i64) {
+        let _ = self.control_tx.lock().unwrap().send((name.to_string(), value));
+    }
+
+    // Newest frame - decoded on first use. Fails when the last capture attempt
+    // failed or no frame arrived yet.
+    pub fn latest(&self) -> anyhow::Result<Frame> {
+        let mut last = self.last.lock().unwrap();
+        let raw = {
+            let mut slot = self.slot.lock().unwrap();
+            if let Some(e) = &slot.error {
+                return Err(anyhow!("{}", e));
+            }
+            slot.frame.take()
+        };
+        // a frame in the slot is newer than the last decoded one
+        if let Some((raw, captured_at, seq)) = raw {
+            let image = raw.decode()?;
+            let capture_height = image.height();
+            *last = Some(Frame { image, captured_at, seq, capture_height });
+        }
+        last.clone().ok_or_else(|| anyhow!("No frame captured yet"))
+    }
+
+    // Wait up to `timeout` for a frame captured after the call - fails when a
+    // capture attempt fails first or no frame arrives in time
+    pub fn next(&self, timeout: Duration) -> anyhow::Result<Frame> {
+        {
+            let slot = self.slot.lock().unwrap();
+            let seen = slot.seq;
+            let (slot, result) = self.captured
+                .wait_timeout_while(slot, timeout, |slot| slot.seq == seen && slot.error.is_none())
+                .unwrap();
+            if result.timed_out() {
+                return Err(anyhow!("No frame captured within {:?}", timeout));
+            }
+            if let Some(e) = &slot.error {
+                return Err(anyhow!("{}", e));
+            }
+        }
+        self.latest()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frame_source::SyntheticSource;
+
+    #[test]
+    fn latest_frame_is_handed_out_again() {
+        // one frame per second - the calls below are faster than the source
+        let capture = CaptureThread::spawn(Box::new(SyntheticSource::new(64, 36, 1.0).unwrap())).unwrap();
+        let mut first = capture.latest();
+        for _ in 0..100 {
+            if first.is_ok() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+            first = capture.latest();
+        }
+        let first = first.unwrap();
+        let again = capture.latest().unwrap();
+        assert_eq!((first.seq, again.seq), (1, 1));
+        assert_eq!(again.capture_height, 36);
+    }
+
+    #[test]
+    fn next_waits_for_a_new_frame() {
+        let capture = CaptureThread::spawn(Box::new(SyntheticSource::new(64, 36, 20.0).unwrap())).unwrap();
+        let first = capture.next(Duration::from_secs(1)).unwrap();
+        let second = capture.next(Duration::from_secs(1)).unwrap();
+        assert!(second.seq > first.seq);
+
+        // the source is slower than the timeout
+        let capture = CaptureThread::spawn(Box::new(SyntheticSource::new(64, 36, 0.5).unwrap())).unwrap();
+        capture.next(Duration::from_secs(1)).unwrap();
+        assert!(capture.next(Duration::from_millis(50)).is_err());
+    }
+}
@@ -0,0 +1,696 @@
+//! Checkerboard camera calibration
+//!
+//! Finds the inner corners of a checkerboard in captured images, estimates the
+//! intrinsics with Zhang's closed form solution and refines them together with
+//! the plumb_bob distortion by Levenberg-Marquardt on the reprojection error.
+
+use anyhow::{anyhow, Context};
+use image::{DynamicImage, GenericImageView};
+use nalgebra::{DMatrix, DVector, Matrix3, Rotation3, SymmetricEigen, Vector3};
+
+use crate::calibration::{distort_point, CameraCalibration};
+
+// Smoothing applied before the saddle point response
+const SADDLE_SIGMA: f32 = 2.0;
+// Radius of the circle sampled around a corner candidate
+const RING_RADIUS: f32 = 5.0;
+const RING_SAMPLES: usize = 32;
+// Minimal intensity difference (0..1) across a corner
+const MIN_CORNER_CONTRAST: f32 = 0.15;
+// Largest distance (in squares) of a corner from its grid position
+const MAX_GRID_ERROR: f64 = 0.3;
+const SUBPIXEL_WINDOW: i32 = 5;
+const MAX_ITERATIONS: usize = 100;
+
+// Checkerboard found in one image - corners in row major order
+pub struct BoardView {
+    pub corners: Vec<(f64, f64)>,
+}
+
+// Calibration result
+pub struct CalibrationResult {
+    pub calibration: CameraCalibration,
+    // RMS reprojection error in pixels
+    pub rms: f64,
+}
+
+// Find the inner corners (cols x rows) of a checkerboard
+pub fn find_corners(img: &DynamicImage, board: (usize, usize)) -> Option<Vec<(f64, f64)>> {
+    let gray = Gray::new(img);
+    let smooth = gray.blur(SADDLE_SIGMA);
+    let response = smooth.saddle_response();
+    let max_response = response.iter().cloned().fold(0.0, f32::max);
+    if max_response <= 0.0 {
+        return None;
+    }
+
+    // Saddle points that look like X junctions, strongest first
+    let fine = gray.blur(1.0);
+    let radius = RING_RADIUS as i32;
+    let mut candidates: Vec<(f32, (f64, f64))> = Vec::new();
+    for y in radius + 1..gray.height as i32 - radius - 1 {
+        for x in radius + 1..gray.width as i32 - radius - 1 {
+            let value = response[gray.index(x, y)];
+            if value < 0.02 * max_response || !is_local_max(&response, &gray, x, y, radius) {
+                continue;
+            }
+            if is_x_junction(&fine, x as f32, y as f32) {
+                candidates.push((value, (x as f64, y as f64)));
+            }
+        }
+    }
+    let count = board.0 * board.1;
+    if candidates.len() < count {
+        return None;
+    }
+    candidates.sort_by(|a, b| b.0.total_cmp(&a.0));
+    let points: Vec<(f64, f64)> = candidates.into_iter().take(count).map(|(_, p)| p).collect();
+
+    let ordered = order_grid(&points, board)?;
+    Some(ordered.into_iter().map(|p| fine.refine_corner(p)).collect())
+}
+
+fn is_local_max(response: &[f32], gray: &Gray, x: i32, y: i32, radius: i32) -> bool {
+    let value = response[gray.index(x, y)];
+    for dy in -radius..=radius {
+        for dx in -radius..=radius {
+            if (dx, dy) == (0, 0) {
+                continue;
+            }
+            let other = response[gray.index(x + dx, y + dy)];
+            // Ties are broken towards the first pixel in scan order
+            if other > value || (other == value && (dy < 0 || (dy == 0 && dx < 0))) {
+                return false;
+            }
+        }
+    }
+    true
+}
+
+// A checkerboard corner seen on a circle is dark, light, dark, light
+// with opposite sectors of the same color
+fn is_x_junction(gray: &Gray, x: f32, y: f32) -> bool {
+    let samples: Vec<f32> = (0..RING_SAMPLES)
+        .map(|i| {
+            let angle = i as f32 * std::f32::consts::TAU / RING_SAMPLES as f32;
+            gray.sample(x + RING_RADIUS * angle.cos(), y + RING_RADIUS * angle.sin())
+        })
+        .collect();
+    let min = samples.iter().cloned().fold(f32::MAX, f32::min);
+    let max = samples.iter().cloned().fold(f32::MIN, f32::max);
+    if max - min < MIN_CORNER_CONTRAST {
+        return false;
+    }
+    let mid = (min + max) / 2.0;
+    let light: Vec<bool> = samples.iter().map(|s| *s > mid).collect();
+    let changes = (0..RING_SAMPLES).filter(|i| light[*i] != light[(i + 1) % RING_SAMPLES]).count();
+    let half = RING_SAMPLES / 2;
+    let symmetric = (0..half).filter(|i| light[*i] == light[i + half]).count();
+    changes == 4 && symmetric * 4 >= half * 3
+}
+
+// Assign the corners to their board positions - the four outer corners of the
+// convex hull give a homography, every corner must land close to a grid position
+fn order_grid(points: &[(f64, f64)], (cols, rows): (usize, usize)) -> Option<Vec<(f64, f64)>> {
+    let hull = convex_hull(points);
+    let quad = largest_quad(&hull)?;
+    let grid_quad = [(0.0, 0.0), ((cols - 1) as f64, 0.0), ((cols - 1) as f64, (rows - 1) as f64), (0.0, (rows - 1) as f64)];
+
+    let mut best: Option<(f64, Vec<(f64, f64)>)> = None;
+    for reversed in [false, true] {
+        for start in 0..4 {
+            let image_quad: Vec<(f64, f64)> = (0..4)
+                .map(|i| if reversed { quad[(start + 4 - i) % 4] } else { quad[(start + i) % 4] })
+                .collect();
+            let to_grid = match homography(&image_quad, &grid_quad) {
+                Some(h) => h,
+                None => continue,
+            };
+            let mut slots: Vec<Option<(f64, f64)>> = vec![None; cols * rows];
+            let mut worst: f64 = 0.0;
+            for point in points {
+                let (gx, gy) = apply_homography(&to_grid, *point);
+                let (i, j) = (gx.round(), gy.round());
+                worst = worst.max((gx - i).hypot(gy - j));
+                if i < 0.0 || j < 0.0 || i >= cols as f64 || j >= rows as f64 {
+                    worst = f64::MAX;
+                    break;
+                }
+                let slot = &mut slots[j as usize * cols + i as usize];
+                if slot.is_some() {
+                    worst = f64::MAX;
+                    break;
+                }
+                *slot = Some(*point);
+            }
+            if worst > MAX_GRID_ERROR || best.as_ref().is_some_and(|(error, _)| *error <= worst) {
+                continue;
+            }
+            best = Some((worst, slots.into_iter().collect::<Option<Vec<_>>>()?));
+        }
+    }
+    best.map(|(_, ordered)| ordered)
+}
+
+// Andrew's monotone chain, counter clockwise
+fn convex_hull(points: &[(f64, f64)]) -> Vec<(f64, f64)> {
+    let mut sorted = points.to_vec();
+    sorted.sort_by(|a, b| a.0.total_cmp(&b.0).then(a.1.total_cmp(&b.1)));
+    let cross = |o: (f64, f64), a: (f64, f64), b: (f64, f64)| (a.0 - o.0) * (b.1 - o.1) - (a.1 - o.1) * (b.0 - o.0);
+    let mut hull: Vec<(f64, f64)> = Vec::new();
+    for pass in 0..2 {
+        let start = hull.len();
+        let iter: Box<dyn Iterator<Item = &(f64, f64)>> = if pass == 0 { Box::new(sorted.iter()) } else { Box::new(sorted.iter().rev()) };
+        for p in iter {
+            while hull.len() >= start + 2 && cross(hull[hull.len() - 2], hull[hull.len() - 1], *p) <= 0.0 {
+                hull.pop();
+            }
+            hull.push(*p);
+        }
+        hull.pop();
+    }
+    hull
+}
+
+// Four hull points spanning the largest area - the board outer corners
+fn largest_quad(hull: &[(f64, f64)]) -> Option<[(f64, f64); 4]> {
+    let n = hull.len();
+    if n < 4 {
+        return None;
+    }
+    let area = |q: [(f64, f64); 4]| {
+        (0..4).map(|i| q[i].0 * q[(i + 1) % 4].1 - q[(i + 1) % 4].0 * q[i].1).sum::<f64>().abs() / 2.0
+    };
+    let mut best = (0.0, [hull[0]; 4]);
+    for a in 0..n {
+        for b in a + 1..n {
+            for c in b + 1..n {
+                for d in c + 1..n {
+                    let quad = [hull[a], hull[b], hull[c], hull[d]];
+                    let quad_area = area(quad);
+                    if quad_area > best.0 {
+                        best = (quad_area, quad);
+                    }
+                }
+            }
+        }
+    }
+    Some(best.1)
+}
+
+// Grayscale image as floats (0..1)
+struct Gray {
+    width: usize,
+    height: usize,
+    data: Vec<f32>,
+}
+
+impl Gray {
+    fn new(img: &DynamicImage) -> Self {
+        let luma = img.to_luma8();
+        Self {
+            width: img.width() as usize,
+            height: img.height() as usize,
+            data: luma.pixels().map(|p| p[0] as f32 / 255.0).collect(),
+        }
+    }
+
+    fn index(&self, x: i32, y: i32) -> usize {
+        y as usize * self.width + x as usize
+    }
+
+    fn at(&self, x: i32, y: i32) -> f32 {
+        let x = x.clamp(0, self.width as i32 - 1);
+        let y = y.clamp(0, self.height as i32 - 1);
+        self.data[self.index(x, y)]
+    }
+
+    fn sample(&self, x: f32, y: f32) -> f32 {
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as i32, y0 as i32);
+        let top = self.at(x0, y0) * (1.0 - fx) + self.at(x0 + 1, y0) * fx;
+        let bottom = self.at(x0, y0 + 1) * (1.0 - fx) + self.at(x0 + 1, y0 + 1) * fx;
+        top * (1.0 - fy) + bottom * fy
+    }
+
+    // Separable gaussian blur
+    fn blur(&self, sigma: f32) -> Gray {
+        let radius = (sigma * 3.0).ceil() as i32;
+        let kernel: Vec<f32> = (-radius..=radius).map(|i| (-(i * i) as f32 / (2.0 * sigma * sigma)).exp()).collect();
+        let norm: f32 = kernel.iter().sum();
+        let pass = |src: &Gray, dx: i32, dy: i32| {
+            let mut data = vec![0.0; src.data.len()];
+            for y in 0..src.height as i32 {
+                for x in 0..src.width as i32 {
+                    let sum: f32 = (-radius..=radius)
+                        .map(|i| kernel[(i + radius) as usize] * src.at(x + i * dx, y + i * dy))
+                        .sum();
+                    data[src.index(x, y)] = sum / norm;
+                }
+            }
+            Gray { width: src.width, height: src.height, data }
+        };
+        pass(&pass(self, 1, 0), 0, 1)
+    }
+
+    // Negative Hessian determinant - large at the saddle of a checkerboard corner
+    fn saddle_response(&self) -> Vec<f32> {
+        let mut response = vec![0.0; self.data.len()];
+        for y in 1..self.height as i32 - 1 {
+            for x in 1..self.width as i32 - 1 {
+                let center = self.at(x, y);
+                let ixx = self.at(x + 1, y) - 2.0 * center + self.at(x - 1, y);
+                let iyy = self.at(x, y + 1) - 2.0 * center + self.at(x, y - 1);
+                let ixy = (self.at(x + 1, y + 1) - self.at(x + 1, y - 1) - self.at(x - 1, y + 1) + self.at(x - 1, y - 1)) / 4.0;
+                response[self.index(x, y)] = (ixy * ixy - ixx * iyy).max(0.0);
+            }
+        }
+        response
+    }
+
+    // Sub-pixel corner position - the point where the image gradients
+    // around the corner are orthogonal to the direction to the corner
+    fn refine_corner(&self, corner: (f64, f64)) -> (f64, f64) {
+        let mut p = corner;
+        for _ in 0..10 {
+            let (cx, cy) = (p.0.round() as i32, p.1.round() as i32);
+            let mut a = nalgebra::Matrix2::<f64>::zeros();
+            let mut b = nalgebra::Vector2::<f64>::zeros();
+            for dy in -SUBPIXEL_WINDOW..=SUBPIXEL_WINDOW {
+                for dx in -SUBPIXEL_WINDOW..=SUBPIXEL_WINDOW {
+                    let (x, y) = (cx + dx, cy + dy);
+                    let gx = ((self.at(x + 1, y) - self.at(x - 1, y)) / 2.0) as f64;
+                    let gy = ((self.at(x, y + 1) - self.at(x, y - 1)) / 2.0) as f64;
+                    let weight = (-((dx * dx + dy * dy) as f64) / (SUBPIXEL_WINDOW * SUBPIXEL_WINDOW) as f64).exp();
+                    let g = nalgebra::Matrix2::new(gx * gx, gx * gy, gx * gy, gy * gy) * weight;
+                    a += g;
+                    b += g * nalgebra::Vector2::new(x as f64, y as f64);
+                }
+            }
+            let next = match a.try_inverse() {
+                Some(inverse) => inverse * b,
+                None => break,
+            };
+            let shift = (next.x - p.0).hypot(next.y - p.1);
+            if shift > SUBPIXEL_WINDOW as f64 {
+                break;
+            }
+            p = (next.x, next.y);
+            if shift < 0.01 {
+                break;
+            }
+        }
+        p
+    }
+}
+
+// Homography mapping `from` to `to` (normalized DLT)
+fn homography(from: &[(f64, f64)], to: &[(f64, f64)]) -> Option<Matrix3<f64>> {
+    if from.len() < 4 || from.len() != to.len() {
+        return None;
+    }
+    let (t_from, t_to) = (normalization(from), normalization(to));
+    let mut a = DMatrix::<f64>::zeros(2 * from.len(), 9);
+    for (i, (p, q)) in from.iter().zip(to).enumerate() {
+        let p = apply_homography(&t_from, *p);
+        let q = apply_homography(&t_to, *q);
+        let rows = [
+            [-p.0, -p.1, -1.0, 0.0, 0.0, 0.0, q.0 * p.0, q.0 * p.1, q.0],
+            [0.0, 0.0, 0.0, -p.0, -p.1, -1.0, q.1 * p.0, q.1 * p.1, q.1],
+        ];
+        for (r, row) in rows.iter().enumerate() {
+            for (c, value) in row.iter().enumerate() {
+                a[(2 * i + r, c)] = *value;
+            }
+        }
+    }
+    let h = null_vector(&a);
+    let h = Matrix3::new(h[0], h[1], h[2], h[3], h[4], h[5], h[6], h[7], h[8]);
+    let h = t_to.try_inverse()? * h * t_from;
+    if h[(2, 2)].abs() < f64::EPSILON {
+        return None;
+    }
+    Some(h / h[(2, 2)])
+}
+
+// Similarity moving the points centroid to the origin with mean distance sqrt(2)
+fn normalization(points: &[(f64, f64)]) -> Matrix3<f64> {
+    let n = points.len() as f64;
+    let (mx, my) = points.iter().fold((0.0, 0.0), |(x, y), p| (x + p.0 / n, y + p.1 / n));
+    let mean_dist = points.iter().map(|p| (p.0 - mx).hypot(p.1 - my)).sum::<f64>() / n;
+    let s = if mean_dist > 0.0 { std::f64::consts::SQRT_2 / mean_dist } else { 1.0 };
+    Matrix3::new(s, 0.0, -s * mx, 0.0, s, -s * my, 0.0, 0.0, 1.0)
+}
+
+fn apply_homography(h: &Matrix3<f64>, (x, y): (f64, f64)) -> (f64, f64) {
+    let p = h * Vector3::new(x, y, 1.0);
+    (p.x / p.z, p.y / p.z)
+}
+
+// Unit vector minimizing |A x| - the eigenvector of A'A with the smallest eigenvalue
+fn null_vector(a: &DMatrix<f64>) -> DVector<f64> {
+    let eigen = SymmetricEigen::new(a.transpose() * a);
+    let (index, _) = eigen.eigenvalues.iter().enumerate()
+        .fold((0, f64::MAX), |best, (i, v)| if *v < best.1 { (i, *v) } else { best });
+    eigen.eigenvectors.column(index).into_owned()
+}
+
+// Zhang's closed form intrinsics (fx, fy, cx, cy) from the board homographies
+fn initial_intrinsics(homographies: &[Matrix3<f64>]) -> Option<Matrix3<f64>> {
+    let v = |h: &Matrix3<f64>, i: usize, j: usize| {
+        [
+            h[(0, i)] * h[(0, j)],
+            h[(0, i)] * h[(1, j)] + h[(1, i)] * h[(0, j)],
+            h[(1, i)] * h[(1, j)],
+            h[(2, i)] * h[(0, j)] + h[(0, i)] * h[(2, j)],
+            h[(2, i)] * h[(1, j)] + h[(1, i)] * h[(2, j)],
+            h[(2, i)] * h[(2, j)],
+        ]
+    };
+    let mut system = DMatrix::<f64>::zeros(2 * homographies.len(), 6);
+    for (n, h) in homographies.iter().enumerate() {
+        let (v12, v11, v22) = (v(h, 0, 1), v(h, 0, 0), v(h, 1, 1));
+        for c in 0..6 {
+            system[(2 * n, c)] = v12[c];
+            system[(2 * n + 1, c)] = v11[c] - v22[c];
+        }
+    }
+    let mut b = null_vector(&system);
+    if b[0] < 0.0 {
+        b = -b;
+    }
+    let (b11, b12, b22, b13, b23, b33) = (b[0], b[1], b[2], b[3], b[4], b[5]);
+    let denominator = b11 * b22 - b12 * b12;
+    if denominator <= 0.0 {
+        return None;
+    }
+    let cy = (b12 * b13 - b11 * b23) / denominator;
+    let lambda = b33 - (b13 * b13 + cy * (b12 * b13 - b11 * b23)) / b11;
+    if lambda / b11 <= 0.0 {
+        return None;
+    }
+    let fx = (lambda / b11).sqrt();
+    let fy = (lambda * b11 / denominator).sqrt();
+    let skew = -b12 * fx * fx * fy / lambda;
+    let cx = skew * cy / fy - b13 * fx * fx / lambda;
+    Some(Matrix3::new(fx, 0.0, cx, 0.0, fy, cy, 0.0, 0.0, 1.0))
+}
+
+// Board pose (rotation vector, translation) from its homography
+fn initial_pose(k: &Matrix3<f64>, h: &Matrix3<f64>) -> Option<(Vector3<f64>, Vector3<f64>)> {
+    let k_inv = k.try_inverse()?;
+    let (h1, h2, h3) = (h.column(0).into_owned(), h.column(1).into_owned(), h.column(2).into_owned());
+    let mut scale = 1.0 / (k_inv * h1).norm();
+    // The board has to be in front of the camera
+    if (k_inv * h3).z < 0.0 {
+        scale = -scale;
+    }
+    let r1 = k_inv * h1 * scale;
+    let r2 = k_inv * h2 * scale;
+    let t = k_inv * h3 * scale;
+    let r = Matrix3::from_columns(&[r1, r2, r1.cross(&r2)]);
+    // Closest rotation matrix
+    let svd = r.svd(true, true);
+    let mut rotation = svd.u? * svd.v_t?;
+    if rotation.determinant() < 0.0 {
+        rotation = -rotation;
+    }
+    Some((Rotation3::from_matrix_unchecked(rotation).scaled_axis(), t))
+}
+
+// Intrinsics followed by the pose of every view
+// [fx, fy, cx, cy, k1, k2, p1, p2, k3, (rx, ry, rz, tx, ty, tz)...]
+const INTRINSIC_PARAMS: usize = 9;
+const POSE_PARAMS: usize = 6;
+
+fn project(params: &[f64], view: usize, object: (f64, f64)) -> (f64, f64) {
+    let (fx, fy, cx, cy) = (params[0], params[1], params[2], params[3]);
+    let d = [params[4], params[5], params[6], params[7], params[8]];
+    let pose = &params[INTRINSIC_PARAMS + view * POSE_PARAMS..];
+    let rotation = Rotation3::new(Vector3::new(pose[0], pose[1], pose[2]));
+    let p = rotation * Vector3::new(object.0, object.1, 0.0) + Vector3::new(pose[3], pose[4], pose[5]);
+    let (x, y) = distort_point(p.x / p.z, p.y / p.z, &d);
+    (fx * x + cx, fy * y + cy)
+}
+
+fn residuals(params: &[f64], views: &[BoardView], objects: &[(f64, f64)]) -> DVector<f64> {
+    let mut r = DVector::zeros(views.len() * objects.len() * 2);
+    for (v, view) in views.iter().enumerate() {
+        for (i, (corner, object)) in view.corners.iter().zip(objects).enumerate() {
+            let (u, w) = project(params, v, *object);
+            let index = 2 * (v * objects.len() + i);
+            r[index] = u - corner.0;
+            r[index + 1] = w - corner.1;
+        }
+    }
+    r
+}
+
+// Parameters estimated by the refinement - k3 is kept at 0 as it is
+// poorly constrained by a handful of views
+fn is_free(param: usize) -> bool {
+    param != 8
+}
+
+// Levenberg-Marquardt minimization of the reprojection error
+fn refine(params: &mut Vec<f64>, views: &[BoardView], objects: &[(f64, f64)]) -> f64 {
+    let mut r = residuals(params, views, objects);
+    let mut cost = r.norm_squared();
+    let mut damping = 1e-3;
+    let points = objects.len() * 2;
+    for _ in 0..MAX_ITERATIONS {
+        // Numeric Jacobian - a pose only moves the corners of its own view
+        let mut jacobian = DMatrix::<f64>::zeros(r.len(), params.len());
+        for p in (0..params.len()).filter(|p| is_free(*p)) {
+            let step = 1e-6 * params[p].abs().max(1e-2);
+            let rows = if p < INTRINSIC_PARAMS {
+                0..r.len()
+            } else {
+                let view = (p - INTRINSIC_PARAMS) / POSE_PARAMS;
+                view * points..(view + 1) * points
+            };
+            let mut shifted = params.clone();
+            shifted[p] += step;
+            let forward = residuals(&shifted, views, objects);
+            shifted[p] -= 2.0 * step;
+            let backward = residuals(&shifted, views, objects);
+            for row in rows {
+                jacobian[(row, p)] = (forward[row] - backward[row]) / (2.0 * step);
+            }
+        }
+        let jt = jacobian.transpose();
+        let jtj = &jt * &jacobian;
+        let gradient = &jt * &r;
+
+        let mut improved = false;
+        while damping < 1e10 {
+            let mut a = jtj.clone();
+            for i in 0..params.len() {
+                a[(i, i)] += damping * jtj[(i, i)].max(1e-9);
+            }
+            let delta = match a.cholesky() {
+                Some(cholesky) => cholesky.solve(&(-&gradient)),
+                None => {
+                    damping *= 10.0;
+                    continue;
+                }
+            };
+            let candidate: Vec<f64> = params.iter().zip(delta.iter()).map(|(p, d)| p + d).collect();
+            let candidate_r = residuals(&candidate, views, objects);
+            let candidate_cost = candidate_r.norm_squared();
+            if candidate_cost < cost {
+                let converged = (cost - candidate_cost) < 1e-12 * cost.max(1.0);
+                *params = candidate;
+                r = candidate_r;
+                cost = candidate_cost;
+                damping = (damping / 10.0).max(1e-12);
+                improved = !converged;
+                break;
+            }
+            damping *= 10.0;
+        }
+        if !improved {
+            break;
+        }
+    }
+    (cost / (r.len() / 2) as f64).sqrt()
+}
+
+// Calibrate from checkerboard views - the board has `board` inner corners
+// and squares of `square` meters
+pub fn calibrate(views: &[BoardView], board: (usize, usize), square: f64, image_size: (u32, u32), camera_name: &str) -> anyhow::Result<CalibrationResult> {
+    if views.len() < 3 {
+        return Err(anyhow!("At least 3 checkerboard views are needed, found {}", views.len()));
+    }
+    let objects: Vec<(f64, f64)> = (0..board.1)
+        .flat_map(|j| (0..board.0).map(move |i| (i as f64 * square, j as f64 * square)))
+        .collect();
+    let homographies = views.iter()
+        .map(|view| homography(&objects, &view.corners))
+        .collect::<Option<Vec<_>>>()
+        .ok_or_else(|| anyhow!("Can't compute the board homography"))?;
+    let k = initial_intrinsics(&homographies)
+        .ok_or_else(|| anyhow!("Can't estimate the intrinsics - use more views with the board tilted in different directions"))?;
+
+    let mut params = vec![k[(0, 0)], k[(1, 1)], k[(0, 2)], k[(1, 2)], 0.0, 0.0, 0.0, 0.0, 0.0];
+    for h in homographies.iter() {
+        let (rotation, translation) = initial_pose(&k, h).ok_or_else(|| anyhow!("Can't estimate the board pose"))?;
+        params.extend(rotation.iter().chain(translation.iter()));
+    }
+    let rms = refine(&mut params, views, &objects);
+
+    let calibration = CameraCalibration::new(
+        camera_name,
+        image_size,
+        (params[0], params[1], params[2], params[3]),
+        [params[4], params[5], params[6], params[7], params[8]],
+    );
+    Ok(CalibrationResult { calibration, rms })
+}
+
+// `calibrate` subcommand - find the board in every image and write the calibration YAML
+pub fn run(pattern: &str, board: (usize, usize), square: f64, output: &str, camera_name: &str) -> anyhow::Result<()> {
+    let mut paths: Vec<_> = glob::glob(pattern)
+        .with_context(|| format!("Bad image pattern {}", pattern))?
+        .filter_map(Result::ok)
+        .collect();
+    paths.sort();
+    if paths.is_empty() {
+        return Err(anyhow!("No calibration images match {}", pattern));
+    }
+
+    let mut views = Vec::new();
+    let mut image_size = None;
+    for path in paths.iter() {
+        let img = image::open(path).with_context(|| format!("Can't read {}", path.display()))?;
+        let size = img.dimensions();
+        if *image_size.get_or_insert(size) != size {
+            return Err(anyhow!("{} is {}x{} - all calibration images must have the same size", path.display(), size.0, size.1));
+        }
+        match find_corners(&img, board) {
+            Some(corners) => {
+                println!("{}: board found", path.display());
+                views.push(BoardView { corners });
+            }
+            None => println!("{}: board not found, skipped", path.display()),
+        }
+    }
+
+    let image_size = image_size.unwrap();
+    let result = calibrate(&views, board, square, image_size, camera_name)?;
+    let (fx, fy, cx, cy) = result.calibration.intrinsics(image_size.0, image_size.1);
+    println!("Calibrated from {} of {} images, RMS reprojection error {:.3} px", views.len(), paths.len(), result.rms);
+    println!("fx {:.2} fy {:.2} cx {:.2} cy {:.2} distortion {:?}", fx, fy, cx, cy, result.calibration.distortion());
+    result.calibration.save(output)?;
+    println!("Camera calibration written to {}", output);
+    Ok(())
+}
+
+// Board size given as COLSxROWS inner corners, e.g. 9x6
+pub fn parse_board(value: &str) -> anyhow::Result<(usize, usize)> {
+    let (cols, rows) = value.split_once('x')
+        .ok_or_else(|| anyhow!("Board size should be COLSxROWS, got {}", value))?;
+    let cols: usize = cols.trim().parse().with_context(|| format!("Bad board size {}", value))?;
+    let rows: usize = rows.trim().parse().with_context(|| format!("Bad board size {}", value))?;
+    if cols < 2 || rows < 2 {
+        return Err(anyhow!("Board needs at least 2x2 inner corners, got {}", value));
+    }
+    Ok((cols, rows))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn calibration_recovers_projected_board() {
+        let board = (9, 6);
+        let square = 0.025;
+        let objects: Vec<(f64, f64)> = (0..board.1)
+            .flat_map(|j| (0..board.0).map(move |i| (i as f64 * square, j as f64 * square)))
+            .collect();
+        // fx, fy, cx, cy, k1, k2, p1, p2, k3
+        let intrinsics = [600.0, 590.0, 320.0, 240.0, -0.2, 0.05, 0.0, 0.0, 0.0];
+        // board tilted in different directions: rotation vector, translation
+        let poses = [
+            [0.3, 0.0, 0.0, -0.1, -0.06, 0.5],
+            [-0.3, 0.1, 0.05, -0.1, -0.06, 0.55],
+            [0.0, 0.35, -0.1, -0.12, -0.05, 0.5],
+            [0.1, -0.35, 0.1, -0.08, -0.07, 0.6],
+            [0.25, 0.25, 0.0, -0.1, -0.08, 0.45],
+        ];
+        let views: Vec<BoardView> = poses.iter().map(|pose| {
+            let params: Vec<f64> = intrinsics.iter().chain(pose.iter()).copied().collect();
+            BoardView { corners: objects.iter().map(|object| project(&params, 0, *object)).collect() }
+        }).collect();
+
+        let result = calibrate(&views, board, square, (640, 480), "test").unwrap();
+        let (fx, fy, cx, cy) = result.calibration.intrinsics(640, 480);
+        let d = result.calibration.distortion();
+        assert!((fx - 600.0).abs() < 1.0, "fx {}", fx);
+        assert!((fy - 590.0).abs() < 1.0, "fy {}", fy);
+        assert!((cx - 320.0).abs() < 1.0, "cx {}", cx);
+        assert!((cy - 240.0).abs() < 1.0, "cy {}", cy);
+        assert!((d[0] + 0.2).abs() < 0.01, "k1 {}", d[0]);
+        assert!(result.rms < 0.01, "rms {}", result.rms);
+    }
+
+    // Checkerboard of (cols, rows) inner corners rotated by `angle` around its first
+    // corner at `origin`, rendered with 4x4 supersampling. Returns the image and the
+    // inner corners in row major order (pixel centers at integer coordinates).
+    fn render_board(board: (usize, usize), square: f64, origin: (f64, f64), angle: f64) -> (DynamicImage, Vec<(f64, f64)>) {
+        let (sin, cos) = angle.sin_cos();
+        let img = image::GrayImage::from_fn(320, 240, |x, y| {
+            let mut light = 0.0;
+            for sample in 0..16 {
+                let px = x as f64 + (sample % 4) as f64 / 4.0 + 0.125 - origin.0;
+                let py = y as f64 + (sample / 4) as f64 / 4.0 + 0.125 - origin.1;
+                // board coordinates in squares - the first inner corner is (0, 0)
+                let (u, v) = ((cos * px + sin * py) / square, (-sin * px + cos * py) / square);
+                let inside = u >= -1.0 && v >= -1.0 && u < board.0 as f64 && v < board.1 as f64;
+                if !inside || (u.floor() + v.floor()) as i64 % 2 != 0 {
+                    light += 1.0 / 16.0;
+                }
+            }
+            image::Luma([(30.0 + 200.0 * light) as u8])
+        });
+        let corners = (0..board.1)
+            .flat_map(|j| (0..board.0).map(move |i| (i as f64 * square, j as f64 * square)))
+            .map(|(u, v)| (origin.0 + cos * u - sin * v - 0.5, origin.1 + sin * u + cos * v - 0.5))
+            .collect();
+        (DynamicImage::ImageLuma8(img), corners)
+    }
+
+    #[test]
+    fn finds_corners_of_rendered_board() {
+        let board = (7, 5);
+        for angle in [0.0, 0.15, -0.3] {
+            let (img, expected) = render_board(board, 24.0, (90.0, 60.0), angle);
+            let corners = find_corners(&img, board).unwrap_or_else(|| panic!("no board found at angle {}", angle));
+            assert_eq!(corners.len(), expected.len());
+            // the grid may start at any outer corner - every corner must match one rendered corner
+            for corner in &corners {
+                let error = expected.iter()
+                    .map(|e| ((e.0 - corner.0).powi(2) + (e.1 - corner.1).powi(2)).sqrt())
+                    .fold(f64::MAX, f64::min);
+                assert!(error < 0.5, "angle {}: corner {:?} is {} px off", angle, corner, error);
+            }
+            // neighbours in a row are one square apart
+            let step = ((corners[1].0 - corners[0].0).powi(2) + (corners[1].1 - corners[0].1).powi(2)).sqrt();
+            assert!((step - 24.0).abs() < 0.5, "angle {}: step {}", angle, step);
+        }
+    }
+
+    #[test]
+    fn no_corners_without_a_board() {
+        let img = DynamicImage::ImageLuma8(image::GrayImage::from_pixel(320, 240, image::Luma([128])));
+        assert!(find_corners(&img, (7, 5)).is_none());
+    }
+
+    #[test]
+    fn calibration_needs_three_views() {
+        let views = vec![BoardView { corners: Vec::new() }, BoardView { corners: Vec::new() }];
+        assert!(calibrate(&views, (9, 6), 0.025, (640, 480), "test").is_err());
+    }
+}
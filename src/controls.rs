@@ -0,0 +1,79 @@
+//! Camera controls
+//!
+//! V4L2 controls (exposure, gain, white balance, ...) exposed as command line
+//! options and as runtime adjustable node parameters.
+
+use rscam::Camera;
+
+pub struct CameraControl {
+    // Parameter name (node parameter "camera.<name>")
+    pub name: &'static str,
+    // Command line option
+    pub option: &'static str,
+    // V4L2 control id
+    pub id: u32,
+    pub help: &'static str,
+}
+
+pub const CONTROLS: [CameraControl; 9] = [
+    CameraControl { name: "exposure_auto", option: "exposure-auto", id: rscam::CID_EXPOSURE_AUTO,
+        help: "Sets the auto exposure mode (0: auto, 1: manual, 2: shutter priority, 3: aperture priority)" },
+    CameraControl { name: "exposure", option: "exposure", id: rscam::CID_EXPOSURE_ABSOLUTE,
+        help: "Sets the manual exposure time in 100us units (needs --exposure-auto 1)" },
+    CameraControl { name: "gain", option: "gain", id: rscam::CID_GAIN,
+        help: "Sets the camera gain" },
+    CameraControl { name: "white_balance_auto", option: "white-balance-auto", id: rscam::CID_AUTO_WHITE_BALANCE,
+        help: "Sets the auto white balance (0: off, 1: on)" },
+    CameraControl { name: "white_balance", option: "white-balance", id: rscam::CID_WHITE_BALANCE_TEMPERATURE,
+        help: "Sets the white balance temperature in Kelvin (needs --white-balance-auto 0)" },
+    CameraControl { name: "brightness", option: "brightness", id: rscam::CID_BRIGHTNESS,
+        help: "Sets the image brightness" },
+    CameraControl { name: "focus_auto", option: "focus-auto", id: rscam::CID_FOCUS_AUTO,
+        help: "Sets the auto focus (0: off, 1: on)" },
+    CameraControl { name: "focus", option: "focus", id: rscam::CID_FOCUS_ABSOLUTE,
+        help: "Sets the manual focus position (needs --focus-auto 0)" },
+    CameraControl { name: "power_line_frequency", option: "power-line-frequency", id: rscam::CID_POWER_LINE_FREQUENCY,
+        help: "Sets the power line frequency filter (0: disabled, 1: 50Hz, 2: 60Hz)" },
+];
+
+pub fn control_by_name(name: &str) -> Option<&'static CameraControl> {
+    CONTROLS.iter().find(|control| control.name == name)
+}
+
+// Current value of an integer, boolean or menu control
+pub fn control_value(camera: &Camera, id: u32) -> Option<i64> {
+    match camera.get_control(id).ok()?.data {
+        rscam::CtrlData::Integer { value, .. } => Some(value as i64),
+        rscam::CtrlData::Integer64 { value, .. } => Some(value),
+        rscam::CtrlData::Boolean { value, .. } => Some(value as i64),
+        rscam::CtrlData::Menu { value, .. } => Some(value as i64),
+        rscam::CtrlData::IntegerMenu { value, .. } => Some(value as i64),
+        _ => None,
+    }
+}
+
+// Human readable list of every control the device supports
+pub fn list_controls(camera: &Camera) -> String {
+    let mut controls = String::new();
+    for control in camera.controls().filter_map(|control| control.ok()) {
+        let description = match &control.data {
+            rscam::CtrlData::Integer { value, default, minimum, maximum, step } =>
+                format!("int {} (default {}, range {}..{} step {})", value, default, minimum, maximum, step),
+            rscam::CtrlData::Integer64 { value, default, minimum, maximum, step } =>
+                format!("int64 {} (default {}, range {}..{} step {})", value, default, minimum, maximum, step),
+            rscam::CtrlData::Boolean { value, default } => format!("bool {} (default {})", value, default),
+            rscam::CtrlData::Menu { value, default, items, .. } => format!("menu {} (default {}: {})", value, default,
+                items.iter().map(|item| format!("{}={}", item.index, item.name)).collect::<Vec<_>>().join(", ")),
+            rscam::CtrlData::IntegerMenu { value, default, items, .. } => format!("menu {} (default {}: {})", value, default,
+                items.iter().map(|item| format!("{}={}", item.index, item.value)).collect::<Vec<_>>().join(", ")),
+            rscam::CtrlData::CtrlClass => continue,
+            other => format!("{:?}", other),
+        };
+        let name = CONTROLS.iter()
+            .find(|known| known.id == control.id)
+            .map(|known| format!(" [--{}]", known.option))
+            .unwrap_or_default();
+        controls.push_str(&format!("  {:<32} {}{}\n", control.name, description, name));
+    }
+    controls
+}
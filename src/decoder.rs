@@ -0,0 +1,247 @@
+//! Model output decoders
+//!
+//! Convert the raw output tensor of the supported detector architectures
+//! into candidate detections. Boxes are returned in model input pixel
+//! coordinates; the detector maps them back to the original image.
+
+use ndarray::{ArrayViewD, Ix3};
+use serde::Deserialize;
+
+use crate::detection::{BBox, Detection};
+use crate::error::DetectError;
+
+// Model architecture - selects the output decoder
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum DecoderKind {
+    // [1, N, 5+nc] - cx,cy,w,h,objectness,class scores
+    Yolov5,
+    // [1, 4+nc, N] - cx,cy,w,h,class scores
+    #[default]
+    Yolov8,
+    // [1, N, 6] - x1,y1,x2,y2,score,class (NMS free)
+    Yolov10,
+    // [1, N, 4+nc] - normalized cx,cy,w,h,class scores (NMS free)
+    Rtdetr,
+}
+
+pub trait OutputDecoder: Send + Sync {
+    // Decode the raw model output into detections scoring at least thr
+    fn decode(&self, output: ArrayViewD<f32>, classes: &[String], input_size: (u32, u32), thr: f32) -> Result<Vec<Detection>, DetectError>;
+
+    // NMS-free architectures already return one box per object
+    fn needs_nms(&self) -> bool {
+        true
+    }
+}
+
+pub fn new_decoder(kind: DecoderKind) -> Box<dyn OutputDecoder> {
+    match kind {
+        DecoderKind::Yolov5 => Box::new(Yolov5Decoder),
+        DecoderKind::Yolov8 => Box::new(Yolov8Decoder),
+        DecoderKind::Yolov10 => Box::new(Yolov10Decoder),
+        DecoderKind::Rtdetr => Box::new(RtDetrDecoder),
+    }
+}
+
+pub struct Yolov5Decoder;
+pub struct Yolov8Decoder;
+pub struct Yolov10Decoder;
+pub struct RtDetrDecoder;
+
+impl OutputDecoder for Yolov5Decoder {
+    fn decode(&self, output: ArrayViewD<f32>, classes: &[String], _input_size: (u32, u32), thr: f32) -> Result<Vec<Detection>, DetectError> {
+        let output = batch_view(output, "YOLOv5")?;
+        let mut boxes = Vec::new();
+        for row in output.rows() {
+            if row.len() < 6 {
+                return Err(DetectError::Output(format!("YOLOv5 output rows should have 5+nc values - got {}", row.len())));
+            }
+            let objectness = row[4];
+            let (class_id, class_prob) = best_class(row.iter().skip(5).copied());
+            let prob = objectness * class_prob;
+            if prob < thr {
+                continue;
+            }
+            if let Some(label) = classes.get(class_id) {
+                boxes.push(Detection::new(class_id, label.clone(), prob, BBox::from_center(row[0], row[1], row[2], row[3])));
+            }
+        }
+        Ok(boxes)
+    }
+}
+
+impl OutputDecoder for Yolov8Decoder {
+    fn decode(&self, output: ArrayViewD<f32>, classes: &[String], _input_size: (u32, u32), thr: f32) -> Result<Vec<Detection>, DetectError> {
+        let output = batch_view(output, "YOLOv8")?;
+        if output.nrows() < 5 {
+            return Err(DetectError::Output(format!("YOLOv8 output should have 4+nc rows - got {}", output.nrows())));
+        }
+        let mut boxes = Vec::new();
+        // One column per anchor
+        for col in output.columns() {
+            //find the index with higest probability of the classes.
+            let (class_id, prob) = best_class(col.iter().skip(4).copied());
+            if prob < thr {
+                continue;
+            }
+            if let Some(label) = classes.get(class_id) {
+                boxes.push(Detection::new(class_id, label.clone(), prob, BBox::from_center(col[0], col[1], col[2], col[3])));
+            }
+        }
+        Ok(boxes)
+    }
+}
+
+impl OutputDecoder for Yolov10Decoder {
+    fn decode(&self, output: ArrayViewD<f32>, classes: &[String], _input_size: (u32, u32), thr: f32) -> Result<Vec<Detection>, DetectError> {
+        let output = batch_view(output, "YOLOv10")?;
+        if output.ncols() != 6 {
+            return Err(DetectError::Output(format!("YOLOv10 output rows should have 6 values - got {}", output.ncols())));
+        }
+        let mut boxes = Vec::new();
+        for row in output.rows() {
+            let prob = row[4];
+            if prob < thr {
+                continue;
+            }
+            let class_id = row[5] as usize;
+            if let Some(label) = classes.get(class_id) {
+                boxes.push(Detection::new(class_id, label.clone(), prob, BBox::new(row[0], row[1], row[2], row[3])));
+            }
+        }
+        Ok(boxes)
+    }
+
+    fn needs_nms(&self) -> bool {
+        false
+    }
+}
+
+impl OutputDecoder for RtDetrDecoder {
+    fn decode(&self, output: ArrayViewD<f32>, classes: &[String], input_size: (u32, u32), thr: f32) -> Result<Vec<Detection>, DetectError> {
+        let output = batch_view(output, "RT-DETR")?;
+        if output.ncols() < 5 {
+            return Err(DetectError::Output(format!("RT-DETR output rows should have 4+nc values - got {}", output.ncols())));
+        }
+        let (input_width, input_height) = (input_size.0 as f32, input_size.1 as f32);
+        let mut boxes = Vec::new();
+        for row in output.rows() {
+            let (class_id, prob) = best_class(row.iter().skip(4).copied());
+            if prob < thr {
+                continue;
+            }
+            if let Some(label) = classes.get(class_id) {
+                // Boxes are normalized to the model input size
+                let bbox = BBox::from_center(row[0] * input_width, row[1] * input_height, row[2] * input_width, row[3] * input_height);
+                boxes.push(Detection::new(class_id, label.clone(), prob, bbox));
+            }
+        }
+        Ok(boxes)
+    }
+
+    fn needs_nms(&self) -> bool {
+        false
+    }
+}
+
+// Drop the batch dimension of a [1, A, B] output
+fn batch_view<'a>(output: ArrayViewD<'a, f32>, arch: &str) -> Result<ndarray::ArrayView2<'a, f32>, DetectError> {
+    let output = output
+        .into_dimensionality::<Ix3>()
+        .map_err(|_| DetectError::Output(format!("{} output should have 3 dimensions", arch)))?;
+    if output.shape()[0] != 1 {
+        return Err(DetectError::Output(format!("{} output should have batch size 1 - got {}", arch, output.shape()[0])));
+    }
+    Ok(output.index_axis_move(ndarray::Axis(0), 0))
+}
+
+// Returns the class index with the highest score and the score
+fn best_class(scores: impl Iterator<Item = f32>) -> (usize, f32) {
+    scores
+        .enumerate()
+        .fold((0, f32::MIN), |best, (index, value)| if value > best.1 { (index, value) } else { best })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ndarray::Array3;
+
+    fn classes() -> Vec<String> {
+        vec!["hen".to_string(), "cone".to_string()]
+    }
+
+    fn decode(kind: DecoderKind, shape: (usize, usize, usize), data: Vec<f32>, input_size: (u32, u32)) -> Vec<Detection> {
+        let output = Array3::from_shape_vec(shape, data).unwrap().into_dyn();
+        new_decoder(kind).decode(output.view(), &classes(), input_size, 0.5).unwrap()
+    }
+
+    #[test]
+    fn yolov5_rows_scale_class_scores_by_objectness() {
+        // [1, N, 5+nc]: cx, cy, w, h, objectness, hen, cone
+        let detections = decode(DecoderKind::Yolov5, (1, 3, 7), vec![
+            100.0, 50.0, 20.0, 10.0, 0.9, 0.2, 0.8,
+            200.0, 80.0, 40.0, 40.0, 0.5, 0.9, 0.1, // 0.45 after objectness
+            300.0, 90.0, 10.0, 30.0, 1.0, 0.6, 0.3,
+        ], (640, 640));
+        assert_eq!(detections.len(), 2);
+        assert_eq!((detections[0].class_id, detections[0].label.as_str()), (1, "cone"));
+        assert!((detections[0].score - 0.72).abs() < 1e-6);
+        assert_eq!(detections[0].bbox, BBox::new(90.0, 45.0, 110.0, 55.0));
+        assert_eq!(detections[1].class_id, 0);
+        assert_eq!(detections[1].bbox, BBox::new(295.0, 75.0, 305.0, 105.0));
+    }
+
+    #[test]
+    fn yolov8_reads_one_anchor_per_column() {
+        // [1, 4+nc, N]: rows cx, cy, w, h, hen, cone - columns are anchors
+        let detections = decode(DecoderKind::Yolov8, (1, 6, 3), vec![
+            100.0, 200.0, 300.0, // cx
+            50.0, 60.0, 70.0,    // cy
+            20.0, 10.0, 40.0,    // w
+            10.0, 20.0, 30.0,    // h
+            0.1, 0.9, 0.2,       // hen
+            0.7, 0.05, 0.3,      // cone
+        ], (640, 640));
+        assert_eq!(detections.len(), 2);
+        assert_eq!((detections[0].class_id, detections[0].score), (1, 0.7));
+        assert_eq!(detections[0].bbox, BBox::new(90.0, 45.0, 110.0, 55.0));
+        assert_eq!((detections[1].class_id, detections[1].score), (0, 0.9));
+        assert_eq!(detections[1].bbox, BBox::new(195.0, 50.0, 205.0, 70.0));
+    }
+
+    #[test]
+    fn yolov8_rejects_too_few_rows() {
+        let output = Array3::<f32>::zeros((1, 4, 10)).into_dyn();
+        assert!(new_decoder(DecoderKind::Yolov8).decode(output.view(), &classes(), (640, 640), 0.5).is_err());
+    }
+
+    #[test]
+    fn yolov10_rows_are_corner_boxes_with_class_id() {
+        // [1, N, 6]: x1, y1, x2, y2, score, class
+        let decoder = new_decoder(DecoderKind::Yolov10);
+        assert!(!decoder.needs_nms());
+        let detections = decode(DecoderKind::Yolov10, (1, 3, 6), vec![
+            10.0, 20.0, 30.0, 60.0, 0.8, 1.0,
+            40.0, 40.0, 50.0, 50.0, 0.3, 0.0,
+            15.0, 25.0, 35.0, 45.0, 0.6, 7.0, // class outside the class list
+        ], (640, 640));
+        assert_eq!(detections.len(), 1);
+        assert_eq!((detections[0].class_id, detections[0].label.as_str(), detections[0].score), (1, "cone", 0.8));
+        assert_eq!(detections[0].bbox, BBox::new(10.0, 20.0, 30.0, 60.0));
+    }
+
+    #[test]
+    fn rtdetr_boxes_are_scaled_from_normalized_centers() {
+        // [1, N, 4+nc]: normalized cx, cy, w, h, hen, cone
+        let detections = decode(DecoderKind::Rtdetr, (1, 2, 6), vec![
+            0.5, 0.25, 0.1, 0.2, 0.1, 0.9,
+            0.1, 0.1, 0.1, 0.1, 0.2, 0.3,
+        ], (640, 320));
+        assert_eq!(detections.len(), 1);
+        assert_eq!((detections[0].class_id, detections[0].score), (1, 0.9));
+        // center (320, 80), size 64x64 in a 640x320 input
+        assert_eq!(detections[0].bbox, BBox::new(288.0, 48.0, 352.0, 112.0));
+    }
+}
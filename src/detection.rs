@@ -0,0 +1,87 @@
+//! Detection types
+//!
+//! Shared by the detector, the distance estimation and the published messages.
+
+use serde::{Serialize, Deserialize};
+
+// Axis aligned bounding box in image pixel coordinates
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct BBox {
+    pub x1: f32,
+    pub y1: f32,
+    pub x2: f32,
+    pub y2: f32,
+}
+
+impl BBox {
+    pub fn new(x1: f32, y1: f32, x2: f32, y2: f32) -> Self {
+        Self { x1, y1, x2, y2 }
+    }
+
+    // Build a box from its center point and size
+    pub fn from_center(xc: f32, yc: f32, w: f32, h: f32) -> Self {
+        Self::new(xc - w / 2.0, yc - h / 2.0, xc + w / 2.0, yc + h / 2.0)
+    }
+
+    pub fn width(&self) -> f32 {
+        self.x2 - self.x1
+    }
+
+    pub fn height(&self) -> f32 {
+        self.y2 - self.y1
+    }
+
+    // Returns the box center as (x, y)
+    pub fn center(&self) -> (f32, f32) {
+        ((self.x1 + self.x2) / 2.0, (self.y1 + self.y2) / 2.0)
+    }
+
+    pub fn area(&self) -> f32 {
+        self.width() * self.height()
+    }
+
+    // Function calculates intersection area of two boxes
+    // Returns Area of intersection of the boxes as a float number (0 for disjoint boxes)
+    pub fn intersection(&self, other: &BBox) -> f32 {
+        let x1 = self.x1.max(other.x1);
+        let y1 = self.y1.max(other.y1);
+        let x2 = self.x2.min(other.x2);
+        let y2 = self.y2.min(other.y2);
+        (x2 - x1).max(0.0) * (y2 - y1).max(0.0)
+    }
+
+    // Function calculates union area of two boxes
+    // Returns Area of the boxes union as a float number
+    pub fn union(&self, other: &BBox) -> f32 {
+        self.area() + other.area() - self.intersection(other)
+    }
+
+    // Function calculates "Intersection-over-union" coefficient for specified two boxes
+    // https://pyimagesearch.com/2016/11/07/intersection-over-union-iou-for-object-detection/.
+    // Returns Intersection over union ratio as a float number
+    pub fn iou(&self, other: &BBox) -> f32 {
+        let union = self.union(other);
+        if union <= 0.0 {
+            return 0.0;
+        }
+        self.intersection(other) / union
+    }
+}
+
+// Single detected object
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Detection {
+    // Index of the class in the model output
+    pub class_id: usize,
+    // Class label
+    pub label: String,
+    // Detection probability
+    pub score: f32,
+    pub bbox: BBox,
+}
+
+impl Detection {
+    pub fn new(class_id: usize, label: String, score: f32, bbox: BBox) -> Self {
+        Self { class_id, label, score, bbox }
+    }
+}
@@ -0,0 +1,53 @@
+//! Detection errors
+//!
+//! Failures of the detection pipeline - reported by the node instead of
+//! panicking inside the timer callback.
+
+use std::fmt;
+
+#[derive(Debug)]
+pub enum DetectError {
+    // Model file could not be loaded or described
+    ModelLoad(String),
+    // ONNX runtime failed to run the model
+    Inference(String),
+    // Model output does not match the expected layout
+    Output(String),
+    // Captured frame could not be decoded
+    Image(image::ImageError),
+    // Distance estimation has no model for this object type
+    UnknownLabel(String),
+}
+
+impl fmt::Display for DetectError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DetectError::ModelLoad(msg) => write!(f, "Failed to load model: {}", msg),
+            DetectError::Inference(msg) => write!(f, "Inference failed: {}", msg),
+            DetectError::Output(msg) => write!(f, "Unexpected model output: {}", msg),
+            DetectError::Image(e) => write!(f, "Failed to decode image: {}", e),
+            DetectError::UnknownLabel(label) => write!(f, "No distance model for object type '{}'", label),
+        }
+    }
+}
+
+impl std::error::Error for DetectError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            DetectError::Image(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<ort::OrtError> for DetectError {
+    fn from(e: ort::OrtError) -> Self {
+        DetectError::Inference(e.to_string())
+    }
+}
+
+impl From<image::ImageError> for DetectError {
+    fn from(e: image::ImageError) -> Self {
+        DetectError::Image(e)
+    }
+}
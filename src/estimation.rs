@@ -1,4 +1,8 @@
 // Distance estimation 
+
+use crate::detection::Detection;
+use crate::error::DetectError;
+
 // Third-degree polynomial fit based on 7 points in Excel - TBD add more points in field experiment
 // dist = A*h^3 + B*h^2 + C*h + D
 
@@ -14,9 +18,16 @@ const D: f64 =  877.331591326026;
 
 const CM_IN_METER: f64 = 100.0;
 
+// Frame height the polynomial was fitted at (640x360 capture) - box heights of
+// other capture sizes are scaled to it
+const REFERENCE_FRAME_HEIGHT: f64 = 360.0;
+
 
 // Function to estimate distance using linear regression parameters -return distnce in [Meter]
-pub fn estimate_distance(pixel_height: f64, otype:&str) -> f64 {
+// frame_height is the height of the captured frame the box was detected in
+pub fn estimate_distance(detection: &Detection, frame_height: u32) -> Result<f64, DetectError> {
+    let pixel_height: f64 = f64::from(detection.bbox.height()) * REFERENCE_FRAME_HEIGHT / f64::from(frame_height.max(1));
+    let otype = detection.label.as_str();
 
     //normalize height to cone height 
     // TODO later on prepare constants per otype 
@@ -29,10 +40,10 @@ pub fn estimate_distance(pixel_height: f64, otype:&str) -> f64 {
 	"person"    =>  {normalized_pixel_height = pixel_height/4.0;}
 	"roktrack"  =>  {normalized_pixel_height = 0.0;}
         "nothing"   =>  {normalized_pixel_height = 0.0;}
-        _ => unreachable!("type not recognaized"), // This case should never happen 
+        _ => return Err(DetectError::UnknownLabel(otype.to_string())),
     }
 
     //let dist=((A*pixel_height.powi(3)+B*pixel_height.powi(2)+C*pixel_height + D) / CM_IN_METER * 100.0).round() / 100.0;
     let dist=((A*normalized_pixel_height.powi(3)+B*normalized_pixel_height.powi(2)+C*normalized_pixel_height + D) / CM_IN_METER * 100.0).round() / 100.0;
-    dist
+    Ok(dist)
 }
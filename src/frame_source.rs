@@ -0,0 +1,232 @@
+//! Frame sources
+//!
+//! Everything the node can take frames from: the USB camera, a replay of
+//! image files or a synthetic generator (for runs without a camera).
+
+use anyhow::{anyhow, Context};
+use image::{DynamicImage, Rgb, RgbImage};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::camera;
+
+// Connection state of a frame source
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SourceState {
+    Connected,
+    Disconnected,
+    Reconnecting,
+}
+
+impl std::fmt::Display for SourceState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SourceState::Connected => write!(f, "connected"),
+            SourceState::Disconnected => write!(f, "disconnected"),
+            SourceState::Reconnecting => write!(f, "reconnecting"),
+        }
+    }
+}
+
+// Frame as delivered by a source. Camera buffers are decoded only when the
+// frame is used - the capture thread drops most frames unseen.
+pub enum RawFrame {
+    Image(DynamicImage),
+    // Image file (JPEG/PNG)
+    File(PathBuf),
+    // Camera buffer in the given pixel format, with the debug snapshot file it is written to
+    Buffer { data: Vec<u8>, format: String, resolution: (u32, u32), snapshot: Option<String> },
+}
+
+impl RawFrame {
+    // Convert to an RGB image and write the debug snapshot
+    pub fn decode(self) -> anyhow::Result<DynamicImage> {
+        match self {
+            RawFrame::Image(image) => Ok(image),
+            RawFrame::File(file) => image::open(&file).with_context(|| format!("Can't load image {}", file.display())),
+            RawFrame::Buffer { data, format, resolution, snapshot } => {
+                let img = camera::frame_to_image(&data, &format, resolution)?;
+                if let Some(path) = snapshot {
+                    // compressed frames are saved as captured - a failed debug
+                    // snapshot doesn't drop the frame
+                    let saved = if camera::is_compressed(&format) {
+                        fs::write(&path, &data).map_err(|e| e.to_string())
+                    } else {
+                        img.save(&path).map_err(|e| e.to_string())
+                    };
+                    if let Err(e) = saved {
+                        eprintln!("Can't write snapshot {}: {}", path, e);
+                    }
+                }
+                Ok(img)
+            }
+        }
+    }
+}
+
+pub trait FrameSource: Send {
+    // Grab the next frame - blocks until a frame is available
+    fn next_frame(&mut self) -> anyhow::Result<RawFrame>;
+
+    // Connection state - sources without a device are always connected
+    fn state(&self) -> SourceState {
+        SourceState::Connected
+    }
+
+    // Capture time of the last frame reported by the device (None: not known,
+    // the frame is stamped when it arrives)
+    fn capture_time(&self) -> Option<SystemTime> {
+        None
+    }
+
+    // Current values of the adjustable controls (name, value)
+    fn controls(&self) -> Vec<(String, i64)> {
+        Vec::new()
+    }
+
+    // Change a control at runtime
+    fn set_control(&mut self, name: &str, _value: i64) -> anyhow::Result<()> {
+        Err(anyhow!("Control {} is not supported by this frame source", name))
+    }
+}
+
+// Pace a source to a fixed frame rate - the capture thread takes frames as
+// fast as the source delivers them
+struct RateLimiter {
+    period: Duration,
+    next: Instant,
+}
+
+impl RateLimiter {
+    fn new(fps: f32) -> anyhow::Result<Self> {
+        if !(fps > 0.0 && fps.is_finite()) {
+            return Err(anyhow!("Replay rate should be above 0 fps - got {}", fps));
+        }
+        Ok(Self { period: Duration::from_secs_f32(1.0 / fps), next: Instant::now() })
+    }
+
+    // Sleep until the next frame is due
+    fn wait(&mut self) {
+        let now = Instant::now();
+        if self.next > now {
+            thread::sleep(self.next - now);
+        }
+        self.next = self.next.max(now) + self.period;
+    }
+}
+
+// Replays JPEG/PNG files from a directory or a glob pattern in a loop
+pub struct ImageDirSource {
+    files: Vec<PathBuf>,
+    index: usize,
+    rate: RateLimiter,
+}
+
+impl ImageDirSource {
+    // path is a directory or a glob pattern (e.g. "frames/*.jpg"),
+    // fps the replay rate (above 0)
+    pub fn new(path: &str, fps: f32) -> anyhow::Result<Self> {
+        let rate = RateLimiter::new(fps)?;
+        let mut files: Vec<PathBuf> = if Path::new(path).is_dir() {
+            std::fs::read_dir(path)
+                .with_context(|| format!("Can't read image directory {}", path))?
+                .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                .filter(|file| is_image(file))
+                .collect()
+        } else {
+            glob::glob(path)
+                .with_context(|| format!("Bad image pattern {}", path))?
+                .filter_map(|file| file.ok())
+                .filter(|file| is_image(file))
+                .collect()
+        };
+        if files.is_empty() {
+            return Err(anyhow!("No images found in {}", path));
+        }
+        files.sort();
+        println!("Replaying {} images from {}", files.len(), path);
+
+        Ok(Self { files, index: 0, rate })
+    }
+}
+
+fn is_image(file: &Path) -> bool {
+    match file.extension().and_then(|ext| ext.to_str()) {
+        Some(ext) => matches!(ext.to_lowercase().as_str(), "jpg" | "jpeg" | "png"),
+        None => false,
+    }
+}
+
+impl FrameSource for ImageDirSource {
+    fn next_frame(&mut self) -> anyhow::Result<RawFrame> {
+        self.rate.wait();
+        let file = self.files[self.index].clone();
+        self.index = (self.index + 1) % self.files.len();
+        // loaded when the frame is used
+        Ok(RawFrame::File(file))
+    }
+}
+
+// Generates frames of an orange cone moving across a grass field
+pub struct SyntheticSource {
+    width: u32,
+    height: u32,
+    frame: u32,
+    rate: RateLimiter,
+}
+
+impl SyntheticSource {
+    // fps is the frame rate (above 0)
+    pub fn new(width: u32, height: u32, fps: f32) -> anyhow::Result<Self> {
+        Ok(Self { width, height, frame: 0, rate: RateLimiter::new(fps)? })
+    }
+}
+
+impl FrameSource for SyntheticSource {
+    fn next_frame(&mut self) -> anyhow::Result<RawFrame> {
+        self.rate.wait();
+        let (width, height) = (self.width, self.height);
+        let horizon = height / 3;
+        // Cone moves 8 pixels per frame and wraps around
+        let cone_height = height / 3;
+        let cone_width = cone_height / 2;
+        let cone_x = (self.frame * 8) % width.max(1);
+        let cone_top = height - cone_height - height / 10;
+        self.frame = self.frame.wrapping_add(1);
+
+        let img = RgbImage::from_fn(width, height, |x, y| {
+            if y >= cone_top && y < cone_top + cone_height {
+                // Triangle - widens towards the base
+                let half = (y - cone_top) * cone_width / (2 * cone_height.max(1));
+                if x + half >= cone_x && x <= cone_x + half {
+                    return Rgb([255, 110, 0]);
+                }
+            }
+            if y < horizon {
+                Rgb([135, 190, 235])
+            } else {
+                Rgb([40, 120 + ((x ^ y) & 0x1f) as u8, 40])
+            }
+        });
+        Ok(RawFrame::Image(DynamicImage::ImageRgb8(img)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn failed_snapshot_keeps_the_frame() {
+        let raw = RawFrame::Buffer {
+            data: vec![128; 8 * 4],
+            format: "GREY".to_string(),
+            resolution: (8, 4),
+            snapshot: Some("/nonexistent/snapshot.png".to_string()),
+        };
+        let img = raw.decode().unwrap();
+        assert_eq!((img.width(), img.height()), (8, 4));
+    }
+}
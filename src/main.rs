@@ -1,44 +1,121 @@
 use std::{
     sync::atomic::{AtomicUsize, Ordering},
-    time::{SystemTime,Duration},
+    sync::{Arc, Mutex, RwLock},
+    time::{Duration, SystemTime},
 };
-use image::{DynamicImage, GenericImageView, ImageOutputFormat,imageops::FilterType, ColorType};
-use std::io::Cursor;
+use image::{DynamicImage, GenericImageView, imageops::FilterType, ColorType};
 
+use std::collections::{BTreeMap, HashSet};
 use std::env;
 use std::process;
+use anyhow::anyhow;
 use clap::{App, Arg}; // handle arguments 
 
 use rclrust::{qos::QoSProfile, rclrust_info};
 use rclrust_msg::std_msgs::msg::String as String_;
+use rclrust_msg::std_srvs::srv::{Trigger, Trigger_Response};
 
 use serde::{Serialize, Deserialize};
 use serde_json;
 
 //image topic 
 //use rclrust_msg::sensor_msgs::msg::Image as ImageMsg;
-use rclrust_msg::sensor_msgs::msg::CompressedImage as CompressedImageMsg;
-
-//use rclrust_msg::std_msgs::msg::Header;
 
+pub mod annotate;
+pub mod calibration;
 pub mod camera;
+pub mod checkerboard;
+pub mod controls;
+pub mod capture;
+pub mod decoder;
+pub mod detection;
+pub mod error;
 pub mod obj_detect;
 pub mod estimation;
-
-const TOPIC_NAME: &str = "detect";
+pub mod frame_source;
+pub mod models;
+pub mod nms;
+pub mod params;
+pub mod pipeline;
+pub mod probe;
+pub mod topics;
+pub mod transform;
+
+const TRIGGER_SERVICE_NAME: &str = "detect/trigger";
 const FPS: f32 = 0.3; // Frames per second
 const SECONDS_PER_MINUTE: f32 = 60.0;
 const MILLISECONDS_PER_SECOND: f32 = 1000.0;
+// Node parameters are polled at this period - the detection timer runs at the fps rate
+const PARAM_PERIOD_MS: u64 = 200;
 
 #[derive(Serialize, Deserialize, Debug)]
 struct BoxCor(f32, f32, f32, f32);
 
+impl From<&detection::BBox> for BoxCor {
+    fn from(bbox: &detection::BBox) -> Self {
+        BoxCor(bbox.x1, bbox.y1, bbox.x2, bbox.y2)
+    }
+}
+
+// Capture time of the frame - same as the image header stamp
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+struct Stamp {
+    sec: i32,
+    nanosec: u32,
+}
+
+impl From<SystemTime> for Stamp {
+    fn from(time: SystemTime) -> Self {
+        let time = pipeline::ros_time(time);
+        Stamp { sec: time.sec, nanosec: time.nanosec }
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 struct DetObj {
     box_location: BoxCor,
     otype: String,
     prob: f32,
-    dist: f64,
+    // null when there is no distance estimate for the class
+    dist: Option<f64>,
+    stamp: Stamp,
+}
+
+impl DetObj {
+    fn new(detection: &detection::Detection, dist: Option<f64>, stamp: Stamp) -> Self {
+        DetObj {
+            box_location: BoxCor::from(&detection.bbox),
+            otype: detection.label.clone(),
+            prob: detection.score,
+            dist,
+            stamp,
+        }
+    }
+
+    // Sent when nothing was detected
+    fn nothing(stamp: Stamp) -> Self {
+        DetObj {
+            box_location: BoxCor(0.0, 0.0, 0.0, 0.0),
+            otype: "nothing".to_string(),
+            prob: 1.0,
+            dist: Some(0.0),
+            stamp,
+        }
+    }
+}
+
+
+// Detections of a frame per camera name
+type CameraDetections = Vec<(String, Vec<DetObj>)>;
+
+// Trigger response message - the JSON array published on detect, or an object
+// with the array of every camera when several cameras are configured
+fn trigger_message(camera_detections: CameraDetections) -> anyhow::Result<String> {
+    let message = match &camera_detections[..] {
+        [(_, detected_objects)] => serde_json::to_string(detected_objects)?,
+        _ => serde_json::to_string(&camera_detections.into_iter().collect::<BTreeMap<_, _>>())?,
+    };
+    Ok(message)
 }
 
 
@@ -53,29 +130,61 @@ async fn main() -> anyhow::Result<()> {
          .short('f')
          .long("fps")
          .value_name("FPS")
-         .help("Sets the publish rate - fps")
+         .help("Sets the publish rate - fps (0: detect only on detect/trigger service requests)")
          .takes_value(true)
          .required(false)
          .default_value("0.5")
-         .validator(|v| v.parse::<f32>().map(|_| ()).map_err(|_| "FPS must be a float: between 0.0 - 1.0".to_string())))
+         .validator(|v| match v.parse::<f64>() {
+             Ok(fps) if (0.0..=params::MAX_FPS).contains(&fps) => Ok(()),
+             _ => Err(format!("FPS must be a number between 0 - {}", params::MAX_FPS)),
+         }))
     .arg(Arg::new("threshold")
          .short('t')
          .long("thr")
          .value_name("THR")
-         .help("Sets the detection threshold. Defualt:0.4")
+         .help("Sets the detection threshold. Defualt: model threshold from the model manifest")
          .takes_value(true)
          .required(false)
-         .default_value("0.4")
          .validator(|v| v.parse::<f32>().map(|_| ()).map_err(|_| "FPS must be a float: between 0.0 - 1.0".to_string())))
     .arg(Arg::new("model")
          .short('a')
          .long("model")
          .value_name("MODEL")
-         .help("Sets the AI model: A,B (A:yolov8n_hen_bucket_cone_640 B:roktrack_yolov8_nano_fixed_640_640)")
+         .help("Sets the AI model by name or alias from the model manifest. Default: first model listed")
+         .takes_value(true)
+         .required(false))
+    .arg(Arg::new("models")
+         .long("models")
+         .value_name("FILE")
+         .help("Sets the model manifest file")
+         .takes_value(true)
+         .required(false)
+         .default_value("config/models.yaml"))
+    .arg(Arg::new("iou")
+         .long("iou")
+         .value_name("IOU")
+         .help("Sets the NMS IoU threshold. Default: model iou_threshold from the model manifest")
+         .takes_value(true)
+         .required(false)
+         .validator(|v| v.parse::<f32>().map(|_| ()).map_err(|_| "IoU must be a float: between 0.0 - 1.0".to_string())))
+    .arg(Arg::new("nms")
+         .long("nms")
+         .value_name("NMS")
+         .help("Sets the NMS mode: class,agnostic (class: boxes suppress only boxes of the same class, agnostic: any class)")
+         .takes_value(true)
+         .required(false)
+         .default_value("class")
+         .possible_values(["class","agnostic"]))
+    .arg(Arg::new("soft_nms")
+         .long("soft-nms")
+         .value_name("SIGMA")
+         .help("Enables Gaussian Soft-NMS with the given sigma instead of dropping overlapping boxes")
          .takes_value(true)
          .required(false)
-         .default_value("A")  
-         .possible_values(&["A","B"])) 
+         .validator(|v| match v.parse::<f32>() {
+             Ok(sigma) if sigma > 0.0 => Ok(()),
+             _ => Err("Soft-NMS sigma must be a float above 0".to_string()),
+         }))
     .arg(Arg::new("mode")
          .short('m')
          .long("mode")
@@ -85,199 +194,592 @@ async fn main() -> anyhow::Result<()> {
          .required(false)
          .default_value("high")  // Default FPS value
          .possible_values(&["none","low", "med", "high"]))     
+    .arg(Arg::new("source")
+         .short('s')
+         .long("source")
+         .value_name("SOURCE")
+         .help("Sets the frame source: camera,images,synthetic (camera: USB camera, images: replay of --images files, synthetic: generated frames)")
+         .takes_value(true)
+         .required(false)
+         .default_value("camera")
+         .possible_values(["camera","images","synthetic"]))
+    .arg(Arg::new("images")
+         .long("images")
+         .value_name("PATH")
+         .help("Sets the directory or glob pattern (e.g. 'frames/*.jpg') replayed by the images source")
+         .takes_value(true)
+         .required_if_eq("source", "images"))
+    .arg(Arg::new("replay_fps")
+         .long("replay-fps")
+         .value_name("FPS")
+         .help("Sets the frame rate of the images and synthetic sources. Default: 10")
+         .takes_value(true)
+         .required(false)
+         .default_value("10")
+         .validator(|v| match v.parse::<f32>() {
+             Ok(fps) if fps > 0.0 && fps.is_finite() => Ok(()),
+             _ => Err("Replay FPS must be a number above 0".to_string()),
+         }))
+    .arg(Arg::new("camera_config")
+         .long("camera-config")
+         .value_name("FILE")
+         .help("Loads the camera settings (device, resolution, interval, format, nbuffers) of one camera or a list of cameras from a YAML file - command line options override the settings of a single camera")
+         .takes_value(true)
+         .required(false))
+    .arg(Arg::new("frame_id")
+         .long("frame-id")
+         .value_name("FRAME")
+         .help("Sets the frame_id of the published messages. Default: camera")
+         .takes_value(true)
+         .required(false))
+    .arg(Arg::new("topics_config")
+         .long("topics-config")
+         .value_name("FILE")
+         .help("Loads the topic names and QoS profiles (sensor_data, reliable or best_effort, history depth) from a YAML file - see config/topics.yaml")
+         .takes_value(true)
+         .required(false))
+    .arg(Arg::new("device")
+         .short('d')
+         .long("device")
+         .value_name("DEVICE")
+         .help("Sets the camera device, e.g. /dev/video0 or /dev/v4l/by-id/... Default: probe /dev/video5, /dev/video0")
+         .takes_value(true)
+         .required(false))
+    .arg(Arg::new("resolution")
+         .short('r')
+         .long("resolution")
+         .value_name("WxH")
+         .help("Sets the camera capture resolution. Default: 640x360")
+         .takes_value(true)
+         .required(false)
+         .validator(|v| camera::parse_resolution(v).map(|_| ())))
+    .arg(Arg::new("camera_fps")
+         .long("camera-fps")
+         .value_name("FPS")
+         .help("Sets the camera frame rate (frame interval 1/FPS). Default: 30")
+         .takes_value(true)
+         .required(false)
+         .validator(|v| v.parse::<u32>().map(|_| ()).map_err(|_| "Camera FPS must be an integer".to_string())))
+    .arg(Arg::new("format")
+         .long("format")
+         .value_name("FOURCC")
+         .help("Sets the camera pixel format: MJPG,JPEG,YUYV,GREY,RGB3. Default: MJPG")
+         .takes_value(true)
+         .required(false))
+    .arg(Arg::new("buffers")
+         .long("buffers")
+         .value_name("N")
+         .help("Sets the number of camera driver buffers. Default: 1")
+         .takes_value(true)
+         .required(false)
+         .validator(|v| v.parse::<u32>().map(|_| ()).map_err(|_| "Buffers must be an integer".to_string())))
+    .args(controls::CONTROLS.iter().map(|control| Arg::new(control.name)
+         .long(control.option)
+         .value_name("VALUE")
+         .help(control.help)
+         .takes_value(true)
+         .required(false)
+         .validator(|v| v.parse::<i64>().map(|_| ()).map_err(|_| "Control value must be an integer".to_string()))))
+    .arg(Arg::new("list_controls")
+         .long("list-controls")
+         .help("Lists the controls and modes supported by the camera device and exits")
+         .takes_value(false)
+         .required(false))
+    .arg(Arg::new("snapshot")
+         .long("snapshot")
+         .value_name("FILE")
+         .help("Saves every camera frame used for detection to FILE for debugging")
+         .takes_value(true)
+         .required(false))
+    .arg(Arg::new("calibration")
+         .long("calibration")
+         .value_name("FILE")
+         .help("Loads the camera calibration (ROS camera YAML) and publishes it on camera_info")
+         .takes_value(true)
+         .required(false))
+    .arg(Arg::new("undistort")
+         .long("undistort")
+         .help("Removes the lens distortion from frames before detection and publishing")
+         .takes_value(false)
+         .required(false)
+         .requires("calibration"))
+    .arg(Arg::new("rotate")
+         .long("rotate")
+         .value_name("DEGREES")
+         .help("Rotates the captured frames clockwise: 0, 90, 180 or 270")
+         .takes_value(true)
+         .required(false)
+         .possible_values(["0", "90", "180", "270"]))
+    .arg(Arg::new("hflip")
+         .long("hflip")
+         .help("Mirrors the captured frames left-right (after the rotation)")
+         .takes_value(false)
+         .required(false))
+    .arg(Arg::new("vflip")
+         .long("vflip")
+         .help("Mirrors the captured frames upside-down (after the rotation)")
+         .takes_value(false)
+         .required(false))
+    .arg(Arg::new("crop")
+         .long("crop")
+         .value_name("X,Y,WIDTH,HEIGHT")
+         .help("Keeps only this rectangle of the rotated and flipped frame - detection boxes are relative to it")
+         .takes_value(true)
+         .required(false)
+         .validator(|v| transform::parse_crop(v).map(|_| ())))
+    .arg(Arg::new("json_output")
+         .long("json-output")
+         .value_name("ON|OFF")
+         .help("Publishes the JSON detection meta data on detect alongside the vision_msgs detections")
+         .takes_value(true)
+         .required(false)
+         .default_value("on")
+         .possible_values(["on","off"]))
+    .arg(Arg::new("annotate")
+         .long("annotate")
+         .help("Publishes the debug image with the detections drawn on it on detect/annotated (same size as --mode)")
+         .takes_value(false)
+         .required(false))
     .arg(Arg::new("verbose")
          .short('v')
          .long("verbose")
          .help("Sets verbosity on")
          .takes_value(false)
          .required(false))
-    .get_matches();
+    .subcommand(App::new("calibrate")
+         .about("Computes the camera calibration from checkerboard images and writes it as a ROS camera YAML")
+         .arg(Arg::new("images")
+              .long("images")
+              .value_name("PATTERN")
+              .help("Sets the glob pattern of the checkerboard images, e.g. 'calib/*.png'")
+              .takes_value(true)
+              .required(true))
+         .arg(Arg::new("board")
+              .long("board")
+              .value_name("COLSxROWS")
+              .help("Sets the number of inner corners of the checkerboard")
+              .takes_value(true)
+              .default_value("9x6")
+              .validator(|v| checkerboard::parse_board(v).map(|_| ())))
+         .arg(Arg::new("square")
+              .long("square")
+              .value_name("METERS")
+              .help("Sets the checkerboard square size in meters")
+              .takes_value(true)
+              .default_value("0.025")
+              .validator(|v| v.parse::<f64>().map(|_| ()).map_err(|_| "Square size must be a float".to_string())))
+         .arg(Arg::new("output")
+              .short('o')
+              .long("output")
+              .value_name("FILE")
+              .help("Sets the calibration file written")
+              .takes_value(true)
+              .default_value("config/camera_calibration.yaml"))
+         .arg(Arg::new("name")
+              .long("name")
+              .value_name("NAME")
+              .help("Sets the camera name stored in the calibration file")
+              .takes_value(true)
+              .default_value("camera")))
+    .subcommand(App::new("probe")
+         .about("Lists the video devices with their driver, card name, formats, resolutions and frame rates")
+         .arg(Arg::new("device")
+              .short('d')
+              .long("device")
+              .value_name("DEVICE")
+              .help("Probes only this device. Default: every /dev/video* device")
+              .takes_value(true)
+              .required(false))
+         .arg(Arg::new("write")
+              .short('w')
+              .long("write")
+              .value_name("FILE")
+              .help("Writes a camera config (see config/camera.yaml) for the capture devices found")
+              .takes_value(true)
+              .required(false)))
+    .get_matches_from(params::strip_ros_args(env::args()));
+
+    // Camera probe - no node is started
+    if let Some(probe) = matches.subcommand_matches("probe") {
+        return probe::run(probe.value_of("device"), probe.value_of("write"));
+    }
+
+    // Offline camera calibration - no node is started
+    if let Some(calibrate) = matches.subcommand_matches("calibrate") {
+        return checkerboard::run(
+            calibrate.value_of("images").unwrap(),
+            checkerboard::parse_board(calibrate.value_of("board").unwrap())?,
+            calibrate.value_of("square").unwrap().parse::<f64>().unwrap(),
+            calibrate.value_of("output").unwrap(),
+            calibrate.value_of("name").unwrap(),
+        );
+    }
 
 
 
     let fps = matches.value_of("fps").unwrap().parse::<f32>().unwrap();
     let mode = matches.value_of("mode").unwrap().to_string();
-    let model = matches.value_of("model").unwrap().to_string();
-    let thr = matches.value_of("threshold").unwrap().parse::<f32>().unwrap();
+    let models_file = matches.value_of("models").unwrap().to_string();
     let verbose_mode = matches.is_present("verbose");
+    let outputs = pipeline::OutputOptions {
+        json: matches.value_of("json_output").unwrap() == "on",
+        annotate: matches.is_present("annotate"),
+    };
+    let topics = match matches.value_of("topics_config") {
+        Some(path) => topics::TopicsConfig::load(path)?,
+        None => topics::TopicsConfig::default(),
+    };
+    let mut camera_configs = match matches.value_of("camera_config") {
+        Some(path) => camera::load_configs(path)?,
+        None => vec![camera::CameraConfig::default()],
+    };
+    if let [camera_config] = &mut camera_configs[..] {
+        if let Some(device) = matches.value_of("device") {
+            camera_config.device = Some(device.to_string());
+        }
+        if let Some(frame_id) = matches.value_of("frame_id") {
+            camera_config.frame_id = frame_id.to_string();
+        }
+        if let Some(resolution) = matches.value_of("resolution") {
+            camera_config.resolution = camera::parse_resolution(resolution).unwrap();
+        }
+        if let Some(camera_fps) = matches.value_of("camera_fps") {
+            camera_config.interval = (1, camera_fps.parse::<u32>().unwrap());
+        }
+        if let Some(format) = matches.value_of("format") {
+            camera_config.format = format.to_string();
+        }
+        if let Some(buffers) = matches.value_of("buffers") {
+            camera_config.nbuffers = buffers.parse::<u32>().unwrap();
+        }
+        if let Some(snapshot) = matches.value_of("snapshot") {
+            camera_config.snapshot = Some(snapshot.to_string());
+        }
+        for control in controls::CONTROLS.iter() {
+            if let Some(value) = matches.value_of(control.name) {
+                camera_config.controls.insert(control.name.to_string(), value.parse::<i64>().unwrap());
+            }
+        }
+        if let Some(calibration) = matches.value_of("calibration") {
+            camera_config.calibration = Some(calibration.to_string());
+        }
+        if matches.is_present("undistort") {
+            camera_config.undistort = true;
+        }
+        if let Some(rotate) = matches.value_of("rotate") {
+            camera_config.rotate = rotate.parse::<u32>().unwrap();
+        }
+        if matches.is_present("hflip") {
+            camera_config.hflip = true;
+        }
+        if matches.is_present("vflip") {
+            camera_config.vflip = true;
+        }
+        if let Some(crop) = matches.value_of("crop") {
+            camera_config.crop = Some(transform::parse_crop(crop).unwrap());
+        }
+    } else {
+        println!("{} cameras configured - camera command line options are ignored", camera_configs.len());
+    }
+    if matches.is_present("list_controls") {
+        for camera_config in camera_configs.iter() {
+            camera::print_controls(camera_config.device.as_deref())?;
+        }
+        return Ok(());
+    }
+    let source_name = matches.value_of("source").unwrap().to_string();
+    let replay_fps = matches.value_of("replay_fps").unwrap().parse::<f32>().unwrap();
+
+    let registry = models::ModelRegistry::load(&models_file)?;
+    let default_spec = registry.get(matches.value_of("model"))?;
+    let thr = match matches.value_of("threshold") {
+        Some(thr) => thr.parse::<f32>().unwrap(),
+        None => default_spec.threshold,
+    };
+    // --iou overrides the iou_threshold of every model, also after a model change
+    let iou_thr = matches.value_of("iou").map(|iou| iou.parse::<f32>().unwrap());
+    let nms_mode: nms::NmsMode = matches.value_of("nms").unwrap().parse().unwrap();
+    let soft_sigma = matches.value_of("soft_nms").map(|sigma| sigma.parse::<f32>().unwrap());
+    let nms_config_for = move |spec: &models::ModelSpec| {
+        nms::NmsConfig::new(nms_mode, iou_thr.unwrap_or(spec.iou_threshold)).with_soft_sigma(soft_sigma).map_err(|e| anyhow!(e))
+    };
 
-
-    println!("FPS: {}", fps);
-    println!("Mode: {}", mode);
-    println!("Model: {}", model);
-    println!("Thr: {}",thr);
-    println!("Verbose mode is {}", if verbose_mode { "on" } else { "off" });
-
-    // take a pic
-    let cam = camera::UsbCamera::new();
-    //let mut detect_res :String = String::new();
-   
-   
     let ctx = rclrust::init()?;
     let mut node = ctx.create_node("cam_det_publisher")?;
     let logger = node.logger();
-    let count = AtomicUsize::new(0);
-    let publisher = node.create_publisher::<String_>(TOPIC_NAME, &QoSProfile::default())?;   // detection meta data publisher
-    //let image_publisher = node.create_publisher::<ImageMsg>("camera_image", &QoSProfile::default())?; // actual image publisher
-    let image_publisher = node.create_publisher::<CompressedImageMsg>("Compressed_camera_image", &QoSProfile::default())?;
-
-
-    let period_ms: u64 = (MILLISECONDS_PER_SECOND / fps).round() as u64;
-    println!(">FPS:{fps} period [ms]:{period_ms}");
-    match mode.as_str() {
-        "none" => {
-            println!("No debug image ");
-        },
-        "low" => {
-            println!("Black & white image 320x180");
-        },
-        "med" => {
-            println!("Color image 320x180");
-        },
-        "high" => {
-            println!("Color image 640x360");
+    // fps, threshold, model, mode and verbose are node parameters - the command line values are the defaults
+    let node_params = params::NodeParams::declare(&node, params::NodeSettings {
+        fps: fps as f64,
+        threshold: thr as f64,
+        model: default_spec.name.clone(),
+        mode,
+        verbose: verbose_mode,
+    }, registry.selectable_names())?;
+    let settings = node_params.current();
+    let model_spec = registry.get(Some(&settings.model))?;
+    let nms_config = nms_config_for(model_spec)?;
+
+
+    println!("FPS: {}", settings.fps);
+    println!("Mode: {}", settings.mode);
+    println!("Source: {}", source_name);
+    println!("Model: {}", model_spec.name);
+    println!("Thr: {}",settings.threshold);
+    println!("NMS: {:?}",nms_config);
+    println!("Verbose mode is {}", if settings.verbose { "on" } else { "off" });
+    println!("JSON output is {}", if outputs.json { "on" } else { "off" });
+    println!("Annotated image is {}", if outputs.annotate { "on" } else { "off" });
+
+    // open the frame sources - one per camera, the replay sources use the first camera settings
+    let mut sources: Vec<(camera::CameraConfig, Box<dyn frame_source::FrameSource>)> = Vec::new();
+    match source_name.as_str() {
+        "camera" => {
+            for camera_config in camera_configs {
+                let camera = camera::UsbCamera::new(camera_config.clone())?;
+                sources.push((camera_config, Box::new(camera)));
+            }
         },
-        _ => unreachable!("Mode should be either 'none', 'med', low' or 'high'"), // This case should never happen 
+        "images" => sources.push((camera_configs.remove(0), Box::new(frame_source::ImageDirSource::new(matches.value_of("images").unwrap(), replay_fps)?))),
+        "synthetic" => sources.push((camera_configs.remove(0), Box::new(frame_source::SyntheticSource::new(640, 360, replay_fps)?))),
+        _ => unreachable!("Source should be either 'camera', 'images' or 'synthetic'"), // This case should never happen
+    };
+    // load the AI model once - the session is shared by all cameras and reused by every timer tick
+    // until the model parameter changes
+    let detector = Arc::new(RwLock::new(obj_detect::Detector::new(model_spec, nms_config, settings.verbose)?));
+    //let mut detect_res :String = String::new();
+   
+   
+    let count = AtomicUsize::new(0);
+    let error_publisher = node.create_publisher::<String_>(&topics.errors.name, &topics.errors.qos_profile())?; // pipeline failures publisher
+    // one pipeline (capture, publishers, controls) per camera
+    let pipelines = Arc::new(sources.into_iter()
+        .map(|(camera_config, source)| pipeline::CameraPipeline::new(&node, &camera_config, source, &settings.mode, outputs, &topics))
+        .collect::<anyhow::Result<Vec<_>>>()?);
+    let node_params = Arc::new(node_params);
+    let param_node = node.clone();
+
+
+    let period_ms = |fps: f64| (MILLISECONDS_PER_SECOND / fps as f32).round() as u64;
+    match settings.fps {
+        fps if fps > 0.0 => println!(">FPS:{fps} period [ms]:{}", period_ms(fps)),
+        _ => println!(">FPS:0 detection on {} requests only", TRIGGER_SERVICE_NAME),
     }
 
 
-
-    let _timer = node.create_wall_timer(Duration::from_millis(period_ms), move || {
+    // Log a pipeline failure and publish it - the node keeps running
+    let report_error = move |msg: String| {
+        eprintln!("{}", msg);
+        if let Err(e) = error_publisher.publish(&String_ { data: msg }) {
+            eprintln!("Failed to publish error: {}", e);
+        }
+    };
+
+    // Serializes the timer ticks and the trigger requests
+    let cycle_lock = Mutex::new(());
+    // Labels already reported as having no distance estimate
+    let unknown_labels: Mutex<HashSet<String>> = Mutex::new(HashSet::new());
+
+    // One detection cycle: capture, detect, estimate and publish for every camera.
+    // Runs on the detection timer with the newest frames, and on trigger requests
+    // (fresh = true) with frames captured after the request. Returns the detections per camera.
+    // Shared with the parameter handling below - the cycle takes the originals
+    let for_params = (node_params.clone(), pipelines.clone(), detector.clone(), param_node.clone(), report_error.clone());
+    let cycle = Arc::new(move |fresh: bool| -> anyhow::Result<CameraDetections> {
+        let _cycle = cycle_lock.lock().unwrap();
+        let settings = node_params.current();
         count.fetch_add(1, Ordering::Relaxed);
 
-        
-        // capture image 
-        let image_data = match cam.take_pic() {
-            Ok(data) => data,
-            Err(e) => {
-                eprintln!("Failed to capture image: {}", e);
-                return; // Decide how to handle the error
+        // Capture and publish the newest frame of every camera
+        let mut frames = Vec::new();
+        for pipeline in pipelines.iter() {
+            // Apply camera control parameter changes
+            for (name, value) in pipeline.apply_control_changes(&param_node) {
+                rclrust_info!(logger, "{}: camera control {} -> {}", pipeline.name(), name, value);
             }
-        };
-        // TODO do msg conversion it in parallel to detection stage
-        // Load the image from the captured data
-        let mut img = match image::load_from_memory(&image_data) {
-            Ok(img) => img,
-            Err(e) => {
-                eprintln!("Failed to load image from memory: {}", e);
-                return; // Decide how to handle the error
+            // Publish the camera connection state when it changes
+            if let Some(state) = pipeline.publish_state() {
+                rclrust_info!(logger, "{}: camera state: {}", pipeline.name(), state);
             }
-        };
 
-        // Resize the image to smaller size to save BW
-        let mut disble_image_publisher = false;
-        let mut image_x = 640;
-        let mut image_y = 360;
-
-        match mode.as_str() {
-            "none" => {
-                disble_image_publisher = true;
-            },
-            "low" => {
-                image_x = 320;
-                image_y = 180;
-                img = DynamicImage::ImageLuma8(img.to_luma8()) // Convert the image to grayscale for "low" mode
-            },
-            "med" => {
-                image_x = 320;
-                image_y = 180;
-            },
-            "high" => {
-            },
-            _ => unreachable!("Mode should be either 'none', 'med', low' or 'high'"), // This case should never happen 
+            // take the newest captured image - decoded once, used for both publishing and detection
+            // TODO do msg conversion it in parallel to detection stage
+            let frame = match pipeline.next_frame(fresh) {
+                Ok(frame) => frame,
+                Err(e) => {
+                    report_error(format!("{}: Failed to capture image: {}", pipeline.name(), e));
+                    continue;
+                }
+            };
+            if let Err(e) = pipeline.publish_image(&frame) {
+                eprintln!("{}: {}", pipeline.name(), e);
+            }
+            frames.push((pipeline, frame));
+        }
+        if frames.is_empty() {
+            return Err(anyhow!("No camera frame captured"));
         }
 
-
-        let resized_img = img.resize_exact(image_x, image_y, image::imageops::FilterType::Nearest);
-        // Convert the resized image back to a byte vector
-        let mut resized_data = Vec::new();
-        let mut cursor = Cursor::new(&mut resized_data);
-        match resized_img.write_to(&mut cursor, ImageOutputFormat::Jpeg(80)) { // use quality 80 - TODO check if can decrease/increase
-            Ok(_) => (),
+        // Detect stage - the frames of all cameras go through the model together
+        //println!("Detection starts!");
+        let images: Vec<&DynamicImage> = frames.iter().map(|(_, frame)| &frame.image).collect();
+        let results = match detector.read().unwrap().detect_batch(&images, settings.threshold as f32) {
+            Ok(res) => res,
             Err(e) => {
-                eprintln!("Failed to write resized image to buffer: {}", e);
-                return; // Decide how to handle the error
+                report_error(e.to_string());
+                return Err(e.into());
             }
-        }
-
-        // ROS publisher section
-        // Send MSG Topic of type: CompressedImageMsg
-        let now = SystemTime::now()
-            .duration_since(SystemTime::UNIX_EPOCH)
-            .expect("Time went backwards");
-        let stamp = rclrust_msg::builtin_interfaces::msg::Time {
-            sec: now.as_secs() as i32,
-            nanosec: now.subsec_nanos() as u32,
         };
 
-        let image_message = CompressedImageMsg {
-            header: rclrust_msg::std_msgs::msg::Header {
-                stamp: stamp, 
-                frame_id: "camera".to_string(),
-                ..Default::default()
-            },
-            format: "jpeg".to_string(),  // For JPEG/MJPEG format
-            //data: image_data,          //TODO keep for orig mode.
-            data: resized_data,
-
-        };
-
-        // Publish the image
-        if !disble_image_publisher {
-            match image_publisher.publish(&image_message) {
-                Ok(_) =>  {},//rclrust_info!(logger, "Image published successfully."),
-                Err(e) => eprintln!("Failed to publish image: {}", e),
-            };
-        };
-
-        // Detect stage
-        //println!("Detection starts!");
-        let detect_res = obj_detect::detect("image.jpg",verbose_mode,&model,thr);
-        //process string to DetObj format
-
-        let mut detected_objects: Vec<DetObj> = Vec::new();
-        // Estimate Pylon distance in cm 
-        // Given data points
+        let mut camera_detections = Vec::new();
+        for ((pipeline, frame), detect_res) in frames.iter().zip(results) {
+            //process string to DetObj format
+            let stamp = Stamp::from(frame.captured_at);
+
+            // Estimate Pylon distance in meters - None for classes without an estimate
+            let distances: Vec<(&detection::Detection, Option<f64>)> = detect_res.iter()
+                .map(|detection| {
+                    let dist = match estimation::estimate_distance(detection, frame.capture_height) {
+                        Ok(dist) => Some(dist),
+                        Err(e) => {
+                            // reported once per label - models with other classes (e.g. COCO) hit it on every frame
+                            if unknown_labels.lock().unwrap().insert(detection.label.clone()) {
+                                report_error(e.to_string());
+                            }
+                            None
+                        }
+                    };
+                    (detection, dist)
+                })
+                .collect();
+
+            if let Err(e) = pipeline.publish_detection_array(frame, &distances) {
+                report_error(format!("{}: {}", pipeline.name(), e));
+            }
+            if let Err(e) = pipeline.publish_annotated(frame, &distances) {
+                eprintln!("{}: {}", pipeline.name(), e);
+            }
+            let mut detected_objects: Vec<DetObj> = distances.iter()
+                .map(|(detection, dist)| DetObj::new(detection, *dist, stamp))
+                .collect();
 
+            // Check if detection found something otherwise send nothing found msg
+            if detected_objects.is_empty() {
+                detected_objects.push(DetObj::nothing(stamp));
+            }
+            if !outputs.json {
+                camera_detections.push((pipeline.name().to_string(), detected_objects));
+                continue;
+            }
 
-        for detection in &detect_res {
-            let pixel_height:f64 = (detection.3 - detection.1).into(); 
-            let obj = DetObj {
-                box_location: BoxCor(detection.0, detection.1, detection.2, detection.3),
-                otype: detection.4.to_string(),
-                prob: detection.5,
-                dist: estimation::estimate_distance(pixel_height,detection.4),
+            let serialized_data = match serde_json::to_string(&detected_objects) {
+                Ok(data) => data,
+                Err(e) => {
+                    eprintln!("Failed to serialize detected data: {}", e);
+                    continue;  // Don't proceed if serialization fails
+                }
             };
-            //println!("Object:{:?} Pixel hieght:{}",obj.otype,pixel_height);
-            detected_objects.push(obj);
-        }
-
-        let serialized_data = match serde_json::to_string(&detected_objects) {
-            Ok(data) => data,
-            Err(e) => {
-                eprintln!("Failed to serialize detected data: {}", e);
-                return;  // Don't proceed if serialization fails
-            }
-        };
         
  
 
-        // Publish detection data 
+            // Publish detection data 
         
-        let mut  message = String_ {
-            //data: format!("{:?} {}",detect_res,count.load(Ordering::Relaxed)),
-            data: serialized_data,
-        };
-        // Check if detection found something otherwise send nothing found msg msg 
-        if message.data == "[]" {
-            //println!("No detection");
-            message.data = "[{\"box_location\":[0.0,0.0,0.0,0.0],\"otype\":\"nothing\",\"prob\":1.0,\"dist\":0.0}]".to_string();
+            let message = String_ {
+                //data: format!("{:?} {}",detect_res,count.load(Ordering::Relaxed)),
+                data: serialized_data,
+            };
+            if settings.verbose {
+                rclrust_info!(logger, "{}: Publishing: '{}'", pipeline.name(), message.data);
+            }
+            if let Err(e) = pipeline.publish_detections(&message) {
+                report_error(format!("{}: {}", pipeline.name(), e));
+            }
+            camera_detections.push((pipeline.name().to_string(), detected_objects));
         }
-        if verbose_mode {
-            rclrust_info!(logger, "Publishing: '{}'", message.data);
+        Ok(camera_detections)
+    });
+
+    // Detection timer at the fps period - none with fps 0 (trigger requests only)
+    let timer_node = node.clone();
+    let timer_cycle = cycle.clone();
+    let start_timer = move |fps: f64| {
+        let timer_cycle = timer_cycle.clone();
+        let timer = (fps > 0.0).then(|| timer_node.create_wall_timer(Duration::from_millis(period_ms(fps)), move || {
+            // failures are reported by the cycle
+            let _ = timer_cycle(false);
+        }));
+        timer.transpose()
+    };
+    let detect_timer = Mutex::new(start_timer(settings.fps)?);
+
+    // Apply node parameter changes - invalid values are reported and set back,
+    // a new fps re-creates the detection timer
+    let apply_params = {
+        let (node_params, pipelines, detector, param_node, report_error) = for_params;
+        let logger = node.logger();
+        Arc::new(move || {
+            let (previous, settings) = match node_params.changed(&param_node, &report_error) {
+                Some(change) => change,
+                None => return,
+            };
+            if settings.model != previous.model {
+                // the current model is kept when the new one fails to load
+                let reloaded = registry.get(Some(&settings.model))
+                    .and_then(|spec| Ok(obj_detect::Detector::new(spec, nms_config_for(spec)?, settings.verbose)?));
+                match reloaded {
+                    Ok(reloaded) => {
+                        *detector.write().unwrap() = reloaded;
+                        rclrust_info!(logger, "Model -> {}", settings.model);
+                    }
+                    Err(e) => {
+                        report_error(format!("Failed to load model {}: {} - keeping {}", settings.model, e, previous.model));
+                        if let Err(e) = node_params.restore_model(&param_node, &previous.model) {
+                            report_error(format!("Can't set parameter model back to {}: {}", previous.model, e));
+                        }
+                    }
+                }
+            }
+            if settings.verbose != previous.verbose {
+                detector.write().unwrap().set_verbose(settings.verbose);
+                rclrust_info!(logger, "Verbose mode -> {}", if settings.verbose { "on" } else { "off" });
+            }
+            if settings.fps != previous.fps {
+                match start_timer(settings.fps) {
+                    Ok(timer) => {
+                        // dropping the previous timer stops it
+                        *detect_timer.lock().unwrap() = timer;
+                        rclrust_info!(logger, "FPS -> {} period [ms]: {}", settings.fps, if settings.fps > 0.0 { period_ms(settings.fps) } else { 0 });
+                    }
+                    Err(e) => report_error(format!("Failed to restart the detection timer at {} fps: {}", settings.fps, e)),
+                }
+            }
+            if settings.threshold != previous.threshold {
+                rclrust_info!(logger, "Thr -> {}", settings.threshold);
+            }
+            if settings.mode != previous.mode {
+                for pipeline in pipelines.iter().filter(|pipeline| pipeline.set_mode(&settings.mode)) {
+                    rclrust_info!(logger, "{}: mode -> {}", pipeline.name(), settings.mode);
+                }
+            }
+        })
+    };
+
+    let timer_apply_params = apply_params.clone();
+    let _param_timer = node.create_wall_timer(Duration::from_millis(PARAM_PERIOD_MS), move || timer_apply_params())?;
+
+    // Capture a new frame and detect right away - the response carries the JSON detections
+    let _trigger_service = node.create_service::<Trigger, _>(TRIGGER_SERVICE_NAME, move |_request| {
+        // pending parameter changes apply to this request
+        apply_params();
+        let message = match cycle(true) {
+            Ok(camera_detections) => trigger_message(camera_detections),
+            Err(e) => Err(e),
+        };
+        match message {
+            Ok(message) => Trigger_Response { success: true, message },
+            Err(e) => Trigger_Response { success: false, message: e.to_string() },
         }
-        publisher.publish(&message).unwrap();
-    })?;
+    }, &QoSProfile::services_default())?;
+    println!("Trigger service: {}", TRIGGER_SERVICE_NAME);
 
     node.wait();
 
@@ -0,0 +1,92 @@
+//! Model registry
+//!
+//! Loads the list of available detection models from a YAML manifest
+//! (see config/models.yaml) so new models can be added without a code change.
+
+use anyhow::{anyhow, Context};
+use serde::Deserialize;
+use std::fs;
+
+use crate::decoder::DecoderKind;
+
+pub const DEFAULT_INPUT_SIZE: (u32, u32) = (640, 640);
+const DEFAULT_THRESHOLD: f32 = 0.4;
+const DEFAULT_IOU_THRESHOLD: f32 = 0.7;
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct ModelSpec {
+    // Name used to select the model with --model
+    pub name: String,
+    // Alternative names (e.g. the legacy A/B switch)
+    #[serde(default)]
+    pub aliases: Vec<String>,
+    // ONNX model file
+    pub path: String,
+    // Model architecture: yolov5, yolov8, yolov10 or rtdetr
+    #[serde(default)]
+    pub decoder: DecoderKind,
+    // Class labels in model output order - used when the model metadata has no labels
+    #[serde(default)]
+    pub classes: Vec<String>,
+    // Model input (width, height) - used when the model does not declare its input shape
+    #[serde(default)]
+    pub input_size: Option<(u32, u32)>,
+    // Default detection probability threshold
+    #[serde(default = "default_threshold")]
+    pub threshold: f32,
+    // Default NMS intersection-over-union threshold
+    #[serde(default = "default_iou_threshold")]
+    pub iou_threshold: f32,
+}
+
+fn default_threshold() -> f32 {
+    DEFAULT_THRESHOLD
+}
+
+fn default_iou_threshold() -> f32 {
+    DEFAULT_IOU_THRESHOLD
+}
+
+impl ModelSpec {
+    fn matches(&self, name: &str) -> bool {
+        self.name == name || self.aliases.iter().any(|alias| alias == name)
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ModelRegistry {
+    pub models: Vec<ModelSpec>,
+}
+
+impl ModelRegistry {
+    // Read the model manifest from a YAML file
+    pub fn load(path: &str) -> anyhow::Result<Self> {
+        let text = fs::read_to_string(path)
+            .with_context(|| format!("Can't read model manifest {}", path))?;
+        let registry: ModelRegistry = serde_yaml::from_str(&text)
+            .with_context(|| format!("Can't parse model manifest {}", path))?;
+        if registry.models.is_empty() {
+            return Err(anyhow!("Model manifest {} lists no models", path));
+        }
+        Ok(registry)
+    }
+
+    // Find a model by name or alias. Without a name the first model is used.
+    pub fn get(&self, name: Option<&str>) -> anyhow::Result<&ModelSpec> {
+        match name {
+            None => Ok(&self.models[0]),
+            Some(name) => self.models.iter().find(|spec| spec.matches(name)).ok_or_else(|| {
+                anyhow!("Unknown model '{}' - available models: {}", name, self.names().join(", "))
+            }),
+        }
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        self.models.iter().map(|spec| spec.name.clone()).collect()
+    }
+
+    // Names and aliases accepted by `get`
+    pub fn selectable_names(&self) -> Vec<String> {
+        self.models.iter().flat_map(|spec| std::iter::once(&spec.name).chain(&spec.aliases).cloned()).collect()
+    }
+}
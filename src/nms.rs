@@ -0,0 +1,191 @@
+//! Non-maximum suppression
+//!
+//! Removes duplicated detections of the same object. Boxes are suppressed
+//! within their own class (per-class) or regardless of class (agnostic),
+//! optionally decaying the scores of overlapping boxes (Soft-NMS) instead of
+//! dropping them.
+
+use crate::detection::Detection;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NmsMode {
+    // Boxes only suppress boxes of the same class
+    PerClass,
+    // Boxes suppress each other regardless of class
+    Agnostic,
+}
+
+impl std::str::FromStr for NmsMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "class" => Ok(NmsMode::PerClass),
+            "agnostic" => Ok(NmsMode::Agnostic),
+            _ => Err(format!("NMS mode should be either 'class' or 'agnostic' - got '{}'", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct NmsConfig {
+    pub mode: NmsMode,
+    // Boxes overlapping a kept box by at least this IoU are suppressed
+    pub iou_threshold: f32,
+    // Gaussian Soft-NMS sigma - None for classic (hard) NMS
+    pub soft_sigma: Option<f32>,
+    // Soft-NMS drops boxes whose decayed score falls below this value
+    pub score_threshold: f32,
+}
+
+impl NmsConfig {
+    pub fn new(mode: NmsMode, iou_threshold: f32) -> Self {
+        Self { mode, iou_threshold, soft_sigma: None, score_threshold: 0.0 }
+    }
+
+    // Gaussian Soft-NMS - sigma must be positive, the decay exp(-iou²/sigma) is NaN
+    // for 0 and zeroes the overlapping boxes for negative values
+    pub fn with_soft_sigma(self, soft_sigma: Option<f32>) -> Result<Self, String> {
+        match soft_sigma {
+            Some(sigma) if sigma <= 0.0 || sigma.is_nan() => Err(format!("Soft-NMS sigma must be above 0 - got {}", sigma)),
+            _ => Ok(Self { soft_sigma, ..self }),
+        }
+    }
+}
+
+// Run NMS over the detections
+// Returns the kept detections ordered by descending score
+pub fn nms(detections: Vec<Detection>, config: &NmsConfig) -> Vec<Detection> {
+    let mut result = match config.mode {
+        NmsMode::Agnostic => suppress(detections, config),
+        NmsMode::PerClass => {
+            let mut classes: Vec<usize> = detections.iter().map(|det| det.class_id).collect();
+            classes.sort_unstable();
+            classes.dedup();
+
+            let mut detections = detections;
+            let mut result = Vec::new();
+            for class_id in classes {
+                let (class_dets, rest): (Vec<_>, Vec<_>) =
+                    detections.into_iter().partition(|det| det.class_id == class_id);
+                detections = rest;
+                result.extend(suppress(class_dets, config));
+            }
+            result
+        }
+    };
+    result.sort_by(|det1, det2| det2.score.total_cmp(&det1.score));
+    result
+}
+
+// Greedy suppression of a single group of detections
+fn suppress(mut boxes: Vec<Detection>, config: &NmsConfig) -> Vec<Detection> {
+    let mut result = Vec::new();
+    while !boxes.is_empty() {
+        // Take the highest score box
+        let best = (0..boxes.len())
+            .max_by(|&i, &j| boxes[i].score.total_cmp(&boxes[j].score))
+            .unwrap();
+        let kept = boxes.swap_remove(best);
+
+        match config.soft_sigma {
+            None => boxes.retain(|det| kept.bbox.iou(&det.bbox) < config.iou_threshold),
+            Some(sigma) => {
+                // Gaussian decay of the overlapping boxes scores
+                for det in boxes.iter_mut() {
+                    let iou = kept.bbox.iou(&det.bbox);
+                    if iou >= config.iou_threshold {
+                        det.score *= (-(iou * iou) / sigma).exp();
+                    }
+                }
+                boxes.retain(|det| det.score >= config.score_threshold);
+            }
+        }
+        result.push(kept);
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::detection::BBox;
+
+    fn det(class_id: usize, score: f32, x1: f32, y1: f32, x2: f32, y2: f32) -> Detection {
+        Detection::new(class_id, format!("class{}", class_id), score, BBox::new(x1, y1, x2, y2))
+    }
+
+    #[test]
+    fn disjoint_boxes_do_not_intersect() {
+        // Diagonal boxes - the unclamped width and height are both negative
+        let a = BBox::new(0.0, 0.0, 10.0, 10.0);
+        let b = BBox::new(20.0, 20.0, 30.0, 30.0);
+        assert_eq!(a.intersection(&b), 0.0);
+        assert_eq!(a.iou(&b), 0.0);
+
+        let dets = vec![det(0, 0.9, 0.0, 0.0, 10.0, 10.0), det(0, 0.8, 20.0, 20.0, 30.0, 30.0)];
+        assert_eq!(nms(dets, &NmsConfig::new(NmsMode::Agnostic, 0.1)).len(), 2);
+    }
+
+    #[test]
+    fn touching_boxes_do_not_intersect() {
+        let a = BBox::new(0.0, 0.0, 10.0, 10.0);
+        let b = BBox::new(10.0, 0.0, 20.0, 10.0);
+        assert_eq!(a.intersection(&b), 0.0);
+        assert_eq!(a.iou(&b), 0.0);
+
+        let dets = vec![det(0, 0.9, 0.0, 0.0, 10.0, 10.0), det(0, 0.8, 10.0, 0.0, 20.0, 10.0)];
+        assert_eq!(nms(dets, &NmsConfig::new(NmsMode::PerClass, 0.01)).len(), 2);
+    }
+
+    #[test]
+    fn nested_box_iou_is_area_ratio() {
+        let outer = BBox::new(0.0, 0.0, 10.0, 10.0);
+        let inner = BBox::new(2.5, 2.5, 7.5, 7.5);
+        assert_eq!(outer.intersection(&inner), inner.area());
+        assert!((outer.iou(&inner) - 0.25).abs() < 1e-6);
+
+        let dets = vec![det(0, 0.9, 0.0, 0.0, 10.0, 10.0), det(0, 0.8, 2.5, 2.5, 7.5, 7.5)];
+        assert_eq!(nms(dets.clone(), &NmsConfig::new(NmsMode::PerClass, 0.2)).len(), 1);
+        assert_eq!(nms(dets, &NmsConfig::new(NmsMode::PerClass, 0.3)).len(), 2);
+    }
+
+    #[test]
+    fn per_class_keeps_overlapping_boxes_of_other_classes() {
+        let dets = vec![
+            det(0, 0.9, 0.0, 0.0, 10.0, 10.0),
+            det(1, 0.8, 1.0, 1.0, 10.0, 10.0),
+            det(0, 0.7, 1.0, 0.0, 10.0, 10.0),
+        ];
+        let kept = nms(dets.clone(), &NmsConfig::new(NmsMode::PerClass, 0.5));
+        assert_eq!(kept.iter().map(|d| d.class_id).collect::<Vec<_>>(), vec![0, 1]);
+
+        let kept = nms(dets, &NmsConfig::new(NmsMode::Agnostic, 0.5));
+        assert_eq!(kept.len(), 1);
+        assert_eq!(kept[0].score, 0.9);
+    }
+
+    #[test]
+    fn soft_nms_decays_overlapping_scores() {
+        let dets = vec![det(0, 0.9, 0.0, 0.0, 10.0, 10.0), det(0, 0.8, 1.0, 0.0, 11.0, 10.0)];
+        let config = NmsConfig { soft_sigma: Some(0.5), score_threshold: 0.1, ..NmsConfig::new(NmsMode::PerClass, 0.3) };
+        let kept = nms(dets, &config);
+        assert_eq!(kept.len(), 2);
+        assert_eq!(kept[0].score, 0.9);
+        assert!(kept[1].score < 0.8 && kept[1].score > 0.1);
+
+        // Heavy overlap decays the score below the threshold
+        let dets = vec![det(0, 0.9, 0.0, 0.0, 10.0, 10.0), det(0, 0.2, 0.0, 0.0, 10.0, 10.0)];
+        assert_eq!(nms(dets, &config).len(), 1);
+    }
+
+    #[test]
+    fn soft_nms_rejects_zero_sigma() {
+        let config = NmsConfig::new(NmsMode::Agnostic, 0.3);
+        assert!(config.with_soft_sigma(Some(0.0)).is_err());
+        assert!(config.with_soft_sigma(Some(-1.0)).is_err());
+        assert!(config.with_soft_sigma(Some(f32::NAN)).is_err());
+        assert_eq!(config.with_soft_sigma(Some(0.5)).unwrap().soft_sigma, Some(0.5));
+        assert_eq!(config.with_soft_sigma(None), Ok(config));
+    }
+}
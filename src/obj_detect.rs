@@ -1,9 +1,15 @@
-use std::{sync::Arc, vec};
-use image::{GenericImageView, imageops::FilterType};
-use ndarray::{Array, IxDyn, s, Axis};
-use ort::{Environment,SessionBuilder,Value};
+use std::sync::Arc;
+use image::{DynamicImage, GenericImageView, imageops::FilterType};
+use ndarray::{Array, ArrayViewD, Axis, IxDyn};
+use ort::{Environment,Session,SessionBuilder,Value};
 use std::time::Instant;
 
+use crate::decoder::{self, OutputDecoder};
+use crate::detection::{BBox, Detection};
+use crate::error::DetectError;
+use crate::models::{ModelSpec, DEFAULT_INPUT_SIZE};
+use crate::nms::{self, NmsConfig};
+
 //const PROB_TH: f32 = 0.3;
 //const MODEL: &str = "./roktrack_yolov8_nano_fixed_640_640.onnx";
 // Array of YOLOv8 class labels
@@ -17,152 +23,258 @@ use std::time::Instant;
 //];
 
 
-pub fn detect(file_name: &str,verbose_mode:bool,model:&str,thr:f32)  -> Vec<(f32,f32,f32,f32,&'static str,f32)> {
-    let buf = std::fs::read(file_name).unwrap_or(vec![]);
+// Long lived YOLOv8 detector - the ONNX environment and session are created
+// once at startup and reused for every frame.
+pub struct Detector {
+    // Keeps the ONNX runtime environment alive for the session lifetime
+    _env: Arc<Environment>,
+    session: Session,
+    spec: ModelSpec,
+    // Class labels in model output order
+    classes: Vec<String>,
+    // Model input (width, height)
+    input_size: (u32,u32),
+    // The model accepts several images per run (dynamic batch axis)
+    dynamic_batch: bool,
+    // Raw model output to detections conversion
+    decoder: Box<dyn OutputDecoder>,
+    // Duplicated detections removal
+    nms: NmsConfig,
+    verbose_mode: bool,
+}
+
+impl Detector {
+    // Load the AI model described by the registry entry and prepare the inference session
+    pub fn new(spec:&ModelSpec,nms:NmsConfig,verbose_mode:bool) -> Result<Self, DetectError> {
+        let load_error = |e: ort::OrtError| DetectError::ModelLoad(format!("{}: {}", spec.path, e));
+        let env = Arc::new(Environment::builder().with_name("YOLOv8").build().map_err(load_error)?);
+        let session = SessionBuilder::new(&env).map_err(load_error)?
+            .with_model_from_file(&spec.path).map_err(load_error)?;
+        println!("Loaded model: {} ({})", spec.name, spec.path);
+
+        // Prefer what the model says about itself, fall back to the manifest
+        let classes = match model_classes(&session) {
+            Some(classes) => classes,
+            None if !spec.classes.is_empty() => spec.classes.clone(),
+            None => return Err(DetectError::ModelLoad(format!("Model {} has no class names in its metadata or in the model manifest", spec.name))),
+        };
+        let input_size = model_input_size(&session)
+            .or(spec.input_size)
+            .unwrap_or(DEFAULT_INPUT_SIZE);
+        let dynamic_batch = session.inputs.first().is_some_and(|input| input.dimensions.first() == Some(&None));
+        println!("Model classes: {:?} input: {}x{} batch: {}", classes, input_size.0, input_size.1,
+            if dynamic_batch { "dynamic" } else { "1" });
+
+        Ok(Self { _env: env, session, spec: spec.clone(), classes, input_size, dynamic_batch, decoder: decoder::new_decoder(spec.decoder), nms, verbose_mode })
+    }
+
+    pub fn spec(&self) -> &ModelSpec {
+        &self.spec
+    }
+
+    pub fn classes(&self) -> &[String] {
+        &self.classes
+    }
+
+    pub fn set_verbose(&mut self, verbose_mode: bool) {
+        self.verbose_mode = verbose_mode;
+    }
+
+    // Function receives a decoded image,
+    // passes it through the detection neural network
+    // and returns an array of detected objects
+    // and their bounding boxes
+    // Returns Array of detected objects
+    pub fn detect(&self, img: &DynamicImage, thr:f32) -> Result<Vec<Detection>, DetectError> {
+        let mut results = self.detect_batch(&[img], thr)?;
+        Ok(results.remove(0))
+    }
+
+    // Detect objects in several images - models with a dynamic batch axis
+    // run all images at once, other models run them one by one
+    // Returns the detected objects per image
+    pub fn detect_batch(&self, imgs: &[&DynamicImage], thr:f32) -> Result<Vec<Vec<Detection>>, DetectError> {
+        if imgs.len() > 1 && !self.dynamic_batch {
+            return imgs.iter().map(|img| self.detect(img, thr)).collect();
+        }
+        let (inputs, letterboxes): (Vec<_>, Vec<_>) = imgs.iter().map(|img| prepare_input(img, self.input_size)).unzip();
+        let views: Vec<_> = inputs.iter().map(|input| input.view()).collect();
+        let input = ndarray::concatenate(Axis(0), &views).map_err(|e| DetectError::Inference(e.to_string()))?;
+        let output = self.run_model(input)?;
+        if output.shape().first() != Some(&imgs.len()) {
+            return Err(DetectError::Output(format!("Model output batch {:?} should be {}", output.shape().first(), imgs.len())));
+        }
+        letterboxes.iter().enumerate()
+            .map(|(i, letterbox)| self.postprocess(output.index_axis(Axis(0), i).insert_axis(Axis(0)), letterbox, thr))
+            .collect()
+    }
+
+    // Decode the output of one image (batch of 1) and map the boxes to the image
+    fn postprocess(&self, output: ArrayViewD<f32>, letterbox: &Letterbox, thr:f32) -> Result<Vec<Detection>, DetectError> {
+        let mut candidates = self.decoder.decode(output, &self.classes, self.input_size, thr)?;
+        for det in candidates.iter_mut() {
+            det.bbox = letterbox.to_image(det.bbox);
+        }
+        let mut boxes = if self.decoder.needs_nms() {
+            // Soft-NMS drops boxes whose decayed score falls below the detection threshold
+            let nms_config = NmsConfig { score_threshold: thr, ..self.nms };
+            nms::nms(candidates, &nms_config)
+        } else {
+            candidates.sort_by(|det1, det2| det2.score.total_cmp(&det1.score));
+            candidates
+        };
+        for det in boxes.iter_mut() {
+            det.score = round_to_decimal_places(det.score,1);
+        }
+        if self.verbose_mode {
+            println!("Result: {:?}",boxes);
+        }
+        Ok(boxes)
+    }
 
-    let boxes = detect_objects_on_image(buf,verbose_mode,model,thr);
-    if verbose_mode {
-        println!("Result: {:?}",boxes);
+    // Function used to pass provided input tensor to
+    // the neural network and return result
+    // Returns raw output of the network (first output tensor)
+    fn run_model(&self, input:Array<f32,IxDyn>) -> Result<Array<f32,IxDyn>, DetectError> {
+        let input_as_values = &input.as_standard_layout();
+        let model_inputs = vec![Value::from_array(self.session.allocator(), input_as_values)?];
+        let outputs = if self.verbose_mode {
+            // Measure the time taken for inference
+            let start_time = Instant::now();
+            let outputs = self.session.run(model_inputs)?;
+            // Calculate the elapsed time
+            let elapsed_time = start_time.elapsed();
+            println!("Inference took: {:?}", elapsed_time);
+            outputs
+        } else {
+            self.session.run(model_inputs)?
+        };
+        let output = outputs.first()
+            .ok_or_else(|| DetectError::Output("Model returned no outputs".to_string()))?
+            .try_extract::<f32>()?.view().into_owned();
+        Ok(output)
     }
-    return boxes;
 }
 
-// Function receives an image,
-// passes it through YOLOv8 neural network
-// and returns an array of detected objects
-// and their bounding boxes
-// Returns Array of bounding boxes in format [(x1,y1,x2,y2,object_type,probability),..]
-fn detect_objects_on_image(buf: Vec<u8>,verbose_mode:bool,model:&str,thr:f32) -> Vec<(f32,f32,f32,f32,&'static str,f32)> {
-    let (input,img_width,img_height) = prepare_input(buf);
-    //println!("Pre Runnning inf call");
-    let output = run_model(input, verbose_mode, model);
-    return process_output(output, img_width, img_height, model, thr);
+// Scale and padding applied by the letterbox resize - used to map boxes
+// from model input coordinates back to the original image
+#[derive(Debug, Clone, Copy)]
+struct Letterbox {
+    scale: f32,
+    pad_x: f32,
+    pad_y: f32,
+    img_width: f32,
+    img_height: f32,
+}
+
+impl Letterbox {
+    fn new(img_width: u32, img_height: u32, input_size: (u32,u32)) -> Self {
+        let (input_width, input_height) = input_size;
+        let scale = (input_width as f32 / img_width as f32).min(input_height as f32 / img_height as f32);
+        let (new_width, new_height) = Self::scaled_size(img_width, img_height, scale);
+        Self {
+            scale,
+            pad_x: (input_width.saturating_sub(new_width) / 2) as f32,
+            pad_y: (input_height.saturating_sub(new_height) / 2) as f32,
+            img_width: img_width as f32,
+            img_height: img_height as f32,
+        }
+    }
+
+    fn scaled_size(img_width: u32, img_height: u32, scale: f32) -> (u32, u32) {
+        (((img_width as f32 * scale).round() as u32).max(1), ((img_height as f32 * scale).round() as u32).max(1))
+    }
+
+    // Map a box from model input coordinates to original image coordinates
+    fn to_image(self, bbox: BBox) -> BBox {
+        BBox::new(
+            ((bbox.x1 - self.pad_x) / self.scale).clamp(0.0, self.img_width),
+            ((bbox.y1 - self.pad_y) / self.scale).clamp(0.0, self.img_height),
+            ((bbox.x2 - self.pad_x) / self.scale).clamp(0.0, self.img_width),
+            ((bbox.y2 - self.pad_y) / self.scale).clamp(0.0, self.img_height),
+        )
+    }
 }
 
+// Padding color used by YOLOv8 training (gray 114)
+const LETTERBOX_PAD: f32 = 114.0 / 255.0;
+
 // Function used to convert input image to tensor,
 // required as an input to YOLOv8 object detection
-// network.
-// Returns the input tensor, original image width and height
-fn prepare_input(buf: Vec<u8>) -> (Array<f32,IxDyn>, u32, u32) {
-    //println!("Buf:{:?}",buf);
-    let img = image::load_from_memory(&buf).unwrap();
-    let (img_width, img_height) = (img.width(), img.height());
-    let img = img.resize_exact(640, 640, FilterType::CatmullRom);
-    let mut input = Array::zeros((1, 3, 640, 640)).into_dyn();
+// network. The image is resized keeping its aspect ratio
+// and padded to the model input size (letterbox).
+// Returns the input tensor and the letterbox scale/padding
+fn prepare_input(img: &DynamicImage, input_size: (u32,u32)) -> (Array<f32,IxDyn>, Letterbox) {
+    let letterbox = Letterbox::new(img.width(), img.height(), input_size);
+    let (new_width, new_height) = Letterbox::scaled_size(img.width(), img.height(), letterbox.scale);
+    let img = img.resize_exact(new_width, new_height, FilterType::CatmullRom);
+    let (input_width, input_height) = input_size;
+    let mut input = Array::from_elem((1, 3, input_height as usize, input_width as usize), LETTERBOX_PAD).into_dyn();
+    let (pad_x, pad_y) = (letterbox.pad_x as usize, letterbox.pad_y as usize);
     for pixel in img.pixels() {
-        let x = pixel.0 as usize;
-        let y = pixel.1 as usize;
+        let x = pixel.0 as usize + pad_x;
+        let y = pixel.1 as usize + pad_y;
         let [r,g,b,_] = pixel.2.0;
         input[[0, 0, y, x]] = (r as f32) / 255.0;
         input[[0, 1, y, x]] = (g as f32) / 255.0;
         input[[0, 2, y, x]] = (b as f32) / 255.0;
     };
-    return (input, img_width, img_height);
+    (input, letterbox)
 }
 
-// Function used to pass provided input tensor to
-// YOLOv8 neural network and return result
-// Returns raw output of YOLOv8 network as a single dimension
-// array
-fn run_model(input:Array<f32,IxDyn>,verbose_mode:bool,ai_model:&str) -> Array<f32,IxDyn> {
-    //println!("Pre Runnning inf env ");
-
-    let mut ai_model_name = " ";
-    match ai_model {
-        "A" => {
-            ai_model_name = "./yolov8n_hen_bucket_cone_640.onnx";
-        },
-        "B" => {
-            ai_model_name = "./roktrack_yolov8_nano_fixed_640_640.onnx";
-        },
-        _ => unreachable!("Mode should be either 'A' or 'B'"), // This case should never happen 
-    }
-
-    let env = Arc::new(Environment::builder().with_name("YOLOv8").build().unwrap());
-    //println!("Pre Runnning inf prepare model");
-    let model = SessionBuilder::new(&env).unwrap().with_model_from_file(ai_model_name.to_string()).unwrap();
-    //println!("Pre Runnning inf prepare input");
-    let input_as_values = &input.as_standard_layout();
-    //println!("Original array:\n{:?}", input);
-    let model_inputs = vec![Value::from_array(model.allocator(), input_as_values).unwrap()];
-    //println!("Model Inputs: {:?}", model_inputs);
-    let outputs = if verbose_mode {
-        // Measure the time taken for inference
-        let start_time = Instant::now();
-        //println!("Pre Runnning inf run model ");
-        let outputs = model.run(model_inputs).unwrap();
-        //println!("Pre Runnning inf results {:?}",outputs);
-        // Calculate the elapsed time   
-        let elapsed_time = start_time.elapsed();
-        println!("Inference took: {:?}", elapsed_time);
-        outputs
-    } else {
-        let outputs = model.run(model_inputs).unwrap();
-        outputs
-    };
-    //println!("Model outputs: {:?}",outputs);
-    let output = outputs.get(0).unwrap().try_extract::<f32>().unwrap().view().t().into_owned();
-    //println!("--------------------------");
-    //println!("Model output: {:?}",output);
-    return output;
+// Class labels stored by Ultralytics exports in the "names" metadata entry,
+// formatted as a python dict: {0: 'hen', 1: 'bucket', 2: 'cone'}
+fn model_classes(session: &Session) -> Option<Vec<String>> {
+    let names = session.metadata().ok()?.custom("names").ok()??;
+    parse_names(&names)
+}
+
+// Classes are indexed by id - ids missing from the dict get a placeholder name
+// so the labels stay aligned with the model output
+fn parse_names(names: &str) -> Option<Vec<String>> {
+    let body = names.trim().strip_prefix('{')?.strip_suffix('}')?;
+    let mut classes: Vec<(usize, String)> = Vec::new();
+    let mut rest = body.trim();
+    while !rest.is_empty() {
+        let (index, tail) = rest.split_once(':')?;
+        let index = index.trim().parse::<usize>().ok()?;
+        let tail = tail.trim_start();
+        let quote = tail.chars().next().filter(|c| *c == '\'' || *c == '"')?;
+        let tail = &tail[1..];
+        let end = tail.find(quote)?;
+        classes.push((index, tail[..end].to_string()));
+        rest = tail[end + 1..].trim_start().trim_start_matches(',').trim_start();
+    }
+    let count = classes.iter().map(|(index, _)| index + 1).max()?;
+    let mut names: Vec<Option<String>> = vec![None; count];
+    for (index, name) in classes {
+        if names[index].replace(name).is_some() {
+            return None; // duplicated id
+        }
+    }
+    Some(names.into_iter().enumerate()
+        .map(|(index, name)| name.unwrap_or_else(|| format!("class{}", index)))
+        .collect())
 }
 
-// Function used to convert RAW output from YOLOv8 to an array
-// of detected objects. Each object contain the bounding box of
-// this object, the type of object and the probability
-// Returns array of detected objects in a format [(x1,y1,x2,y2,object_type,probability),..]
-fn process_output(output:Array<f32,IxDyn>,img_width: u32, img_height: u32, model:&str, thr:f32) -> Vec<(f32,f32,f32,f32,&'static str, f32)> {
-
-    let mut yolo_class = [" "," "," "];
-    match model {
-        "A" => {
-            yolo_class = ["hen", "bucket", "cone"];
-        },
-        "B" => {
-            yolo_class = ["pylon", "person", "roktrack"];
-        },
-        _ => unreachable!("Mode should be either 'A' or 'B'"), // This case should never happen 
-    }
-    let mut boxes = Vec::new();
-    let output = output.slice(s![..,..,0]);
-    for row in output.axis_iter(Axis(0)) {
-        
-        let row:Vec<_> = row.iter().map(|x| *x).collect();
-        //find the index with higest probability of of the 80 classes.
-        let (class_id, prob) = row.iter().skip(4).enumerate()
-            .map(|(index,value)| (index,*value))
-            .reduce(|accum, row| if row.1>accum.1 { row } else {accum}).unwrap(); 
-        if prob < thr {
-            continue
+// Model input (width, height) - taken from the input tensor shape [1,3,H,W]
+// and, for models exported with dynamic axes, from the "imgsz" metadata entry [H, W]
+fn model_input_size(session: &Session) -> Option<(u32,u32)> {
+    if let Some(input) = session.inputs.first() {
+        if let [_, _, Some(height), Some(width)] = input.dimensions[..] {
+            return Some((width, height));
         }
-        //println!("Row: {:?}",row);
-        //println!("Class:{class_id}:{prob}");
-        let label = yolo_class[class_id];
-        let xc = row[0]/640.0*(img_width as f32);
-        let yc = row[1]/640.0*(img_height as f32);
-        let w = row[2]/640.0*(img_width as f32);
-        let h = row[3]/640.0*(img_height as f32);
-        let x1 = xc - w/2.0;
-        let x2 = xc + w/2.0;
-        let y1 = yc - h/2.0;
-        let y2 = yc + h/2.0;
-
-        let prob = round_to_decimal_places(prob,1);
-        boxes.push((x1,y1,x2,y2,label,prob));
-    }
-    //println!("Boxes:{:?}",boxes);
-    boxes.sort_by(|box1,box2| box2.5.total_cmp(&box1.5));
-    //println!("Ordered Boxes:{:?}",boxes);
-    let mut result = Vec::new();
-    // Remove duplicated detections - assume hieghest probability is taken in each class
-    // TBD - why the classes are not mixed after we sort with probability 
-    while boxes.len()>0 {
-        result.push(boxes[0]);
-        //println!("Box[0]:{:?}",boxes[0]);
-        //println!("Boxes:{:?}",boxes);
-        boxes = boxes.iter().filter(|box1| iou(&boxes[0],box1) < 0.7).map(|x| *x).collect()
-    }
-    return result;
+    }
+    let imgsz = session.metadata().ok()?.custom("imgsz").ok()??;
+    let sizes: Vec<u32> = imgsz.trim().trim_start_matches('[').trim_end_matches(']')
+        .split(',')
+        .map(|v| v.trim().parse::<u32>())
+        .collect::<Result<_,_>>().ok()?;
+    match sizes[..] {
+        [height, width] => Some((width, height)),
+        [size] => Some((size, size)),
+        _ => None,
+    }
 }
 
 fn round_to_decimal_places(value: f32, decimal_places: usize) -> f32 {
@@ -170,33 +282,38 @@ fn round_to_decimal_places(value: f32, decimal_places: usize) -> f32 {
     (value * multiplier).round() / multiplier
 }
 
-// Function calculates "Intersection-over-union" coefficient for specified two boxes
-// https://pyimagesearch.com/2016/11/07/intersection-over-union-iou-for-object-detection/.
-// Returns Intersection over union ratio as a float number
-fn iou(box1: &(f32, f32, f32, f32, &'static str, f32), box2: &(f32, f32, f32, f32, &'static str, f32)) -> f32 {
-    return intersection(box1, box2) / union(box1, box2);
-}
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-// Function calculates union area of two boxes
-// Returns Area of the boxes union as a float number
-fn union(box1: &(f32, f32, f32, f32, &'static str, f32), box2: &(f32, f32, f32, f32, &'static str, f32)) -> f32 {
-    let (box1_x1,box1_y1,box1_x2,box1_y2,_,_) = *box1;
-    let (box2_x1,box2_y1,box2_x2,box2_y2,_,_) = *box2;
-    let box1_area = (box1_x2-box1_x1)*(box1_y2-box1_y1);
-    let box2_area = (box2_x2-box2_x1)*(box2_y2-box2_y1);
-    return box1_area + box2_area - intersection(box1, box2);
-}
+    fn names(list: &[&str]) -> Option<Vec<String>> {
+        Some(list.iter().map(|name| name.to_string()).collect())
+    }
 
-// Function calculates intersection area of two boxes
-// Returns Area of intersection of the boxes as a float number
-fn intersection(box1: &(f32, f32, f32, f32, &'static str, f32), box2: &(f32, f32, f32, f32, &'static str, f32)) -> f32 {
-    let (box1_x1,box1_y1,box1_x2,box1_y2,_,_) = *box1;
-    let (box2_x1,box2_y1,box2_x2,box2_y2,_,_) = *box2;
-    let x1 = box1_x1.max(box2_x1);
-    let y1 = box1_y1.max(box2_y1);
-    let x2 = box1_x2.min(box2_x2);
-    let y2 = box1_y2.min(box2_y2);
-    return (x2-x1)*(y2-y1);
-}
+    #[test]
+    fn parses_quoted_names() {
+        assert_eq!(parse_names("{0: 'hen', 1: 'bucket', 2: 'cone'}"), names(&["hen", "bucket", "cone"]));
+        // double quotes and spaces inside names
+        assert_eq!(parse_names(r#"{0: "traffic light", 1: 'stop sign'}"#), names(&["traffic light", "stop sign"]));
+        assert_eq!(parse_names("  {0:'a',1:'b',}  "), names(&["a", "b"]));
+    }
 
+    #[test]
+    fn names_are_ordered_by_id() {
+        assert_eq!(parse_names("{2: 'c', 0: 'a', 1: 'b'}"), names(&["a", "b", "c"]));
+        // missing ids keep the following labels at their class index
+        assert_eq!(parse_names("{0: 'a', 3: 'd'}"), names(&["a", "class1", "class2", "d"]));
+    }
 
+    #[test]
+    fn rejects_malformed_names() {
+        assert_eq!(parse_names(""), None);
+        assert_eq!(parse_names("{}"), None);
+        assert_eq!(parse_names("['a', 'b']"), None);
+        assert_eq!(parse_names("{0: a}"), None);
+        assert_eq!(parse_names("{x: 'a'}"), None);
+        assert_eq!(parse_names("{0: 'a"), None);
+        assert_eq!(parse_names("{0: 'a', 1: 'b'"), None);
+        assert_eq!(parse_names("{0: 'a', 0: 'b'}"), None);
+    }
+}
@@ -0,0 +1,235 @@
+//! Node parameters
+//!
+//! Runtime adjustable settings declared as ROS 2 node parameters. Values are
+//! polled a few times per second, so a `ros2 param set` takes effect on the
+//! next frame. Initial values come from the command line and can be overridden
+//! with `--ros-args --params-file config/params.yaml`.
+
+use rclrust::parameter::{Parameter, ParameterValue};
+use rclrust::Node;
+use std::sync::Mutex;
+
+// Prefix of the camera control parameters, e.g. camera.exposure
+const CAMERA_PREFIX: &str = "camera.";
+
+const FPS_PARAM: &str = "fps";
+const THRESHOLD_PARAM: &str = "threshold";
+const MODEL_PARAM: &str = "model";
+const MODE_PARAM: &str = "mode";
+const VERBOSE_PARAM: &str = "verbose";
+
+const MODES: [&str; 4] = ["none", "low", "med", "high"];
+
+// Highest detection rate - the timer period is a whole number of milliseconds
+pub const MAX_FPS: f64 = 1000.0;
+
+// Command line arguments without the ROS arguments (--ros-args ... [--]),
+// which are left to rclrust::init
+pub fn strip_ros_args<I: IntoIterator<Item = String>>(args: I) -> Vec<String> {
+    let mut in_ros_args = false;
+    args.into_iter()
+        .filter(|arg| match arg.as_str() {
+            "--ros-args" => {
+                in_ros_args = true;
+                false
+            }
+            "--" if in_ros_args => {
+                in_ros_args = false;
+                false
+            }
+            _ => !in_ros_args,
+        })
+        .collect()
+}
+
+// Node settings adjustable at runtime
+#[derive(Debug, Clone, PartialEq)]
+pub struct NodeSettings {
+    // Detection rate in frames per second - 0 detects only on trigger requests
+    pub fps: f64,
+    // Detection threshold
+    pub threshold: f64,
+    // Model name or alias from the model manifest
+    pub model: String,
+    // Debug image mode of the cameras without a mode in their config
+    pub mode: String,
+    pub verbose: bool,
+}
+
+// The node settings parameters with their last accepted values
+pub struct NodeParams {
+    settings: Mutex<NodeSettings>,
+    // Last accepted value per parameter - invalid values are set back to it
+    values: Mutex<Vec<(&'static str, ParameterValue)>>,
+    // Model names and aliases of the model manifest
+    models: Vec<String>,
+}
+
+// Integer values are accepted for the double parameters (ros2 param set fps 1)
+fn double_value(value: &ParameterValue) -> Option<f64> {
+    match value {
+        ParameterValue::Double(value) => Some(*value),
+        ParameterValue::Integer(value) => Some(*value as f64),
+        _ => None,
+    }
+}
+
+// Apply a parameter value to the settings - returns an error for invalid values
+fn apply(settings: &mut NodeSettings, name: &str, value: &ParameterValue, models: &[String]) -> Result<(), String> {
+    let invalid = |expected: &str| Err(format!("Parameter {} should be {} - got {:?}", name, expected, value));
+    match (name, value) {
+        (FPS_PARAM, value) => match double_value(value) {
+            Some(fps) if (0.0..=MAX_FPS).contains(&fps) => settings.fps = fps,
+            _ => return invalid(&format!("a number from 0 (no periodic detection) to {}", MAX_FPS)),
+        },
+        (THRESHOLD_PARAM, value) => match double_value(value) {
+            Some(threshold) if (0.0..=1.0).contains(&threshold) => settings.threshold = threshold,
+            _ => return invalid("a number between 0.0 - 1.0"),
+        },
+        (MODEL_PARAM, ParameterValue::String(model)) if models.contains(model) => settings.model = model.clone(),
+        (MODEL_PARAM, _) => return invalid(&format!("one of the models {}", models.join(", "))),
+        (MODE_PARAM, ParameterValue::String(mode)) if MODES.contains(&mode.as_str()) => settings.mode = mode.clone(),
+        (MODE_PARAM, _) => return invalid("none, low, med or high"),
+        (VERBOSE_PARAM, ParameterValue::Bool(verbose)) => settings.verbose = *verbose,
+        _ => return invalid("a boolean"),
+    }
+    Ok(())
+}
+
+impl NodeParams {
+    // Declare the parameters with the command line values as defaults - values
+    // given with --params-file replace them. `models` are the accepted model names.
+    pub fn declare(node: &Node, defaults: NodeSettings, models: Vec<String>) -> anyhow::Result<Self> {
+        let values = vec![
+            (FPS_PARAM, ParameterValue::Double(defaults.fps)),
+            (THRESHOLD_PARAM, ParameterValue::Double(defaults.threshold)),
+            (MODEL_PARAM, ParameterValue::String(defaults.model.clone())),
+            (MODE_PARAM, ParameterValue::String(defaults.mode.clone())),
+            (VERBOSE_PARAM, ParameterValue::Bool(defaults.verbose)),
+        ];
+        for (name, value) in &values {
+            node.declare_parameter(name, value)?;
+        }
+        let params = Self { settings: Mutex::new(defaults), values: Mutex::new(values), models };
+        params.changed(node, |e| eprintln!("{}", e));
+        Ok(params)
+    }
+
+    pub fn current(&self) -> NodeSettings {
+        self.settings.lock().unwrap().clone()
+    }
+
+    // Returns the previous and the new settings when a parameter changed since
+    // the last call. Invalid values are passed to `report` and the parameter is
+    // set back to its last accepted value.
+    pub fn changed(&self, node: &Node, report: impl Fn(String)) -> Option<(NodeSettings, NodeSettings)> {
+        let mut settings = self.settings.lock().unwrap();
+        let mut values = self.values.lock().unwrap();
+        let mut new_settings = settings.clone();
+        for (name, value) in values.iter_mut() {
+            let param = match node.get_parameter(name) {
+                Some(param) => param,
+                None => continue,
+            };
+            if param.value == *value {
+                continue;
+            }
+            match apply(&mut new_settings, name, &param.value, &self.models) {
+                Ok(()) => *value = param.value,
+                Err(e) => {
+                    report(e);
+                    if let Err(e) = node.set_parameter(Parameter { name: name.to_string(), value: value.clone() }) {
+                        report(format!("Can't set parameter {} back to {:?}: {}", name, value, e));
+                    }
+                }
+            }
+        }
+        if new_settings == *settings {
+            return None;
+        }
+        let previous = std::mem::replace(&mut *settings, new_settings.clone());
+        Some((previous, new_settings))
+    }
+
+    // Set the model parameter back to the running model - after a failed reload
+    pub fn restore_model(&self, node: &Node, model: &str) -> anyhow::Result<()> {
+        let value = ParameterValue::String(model.to_string());
+        node.set_parameter(Parameter { name: MODEL_PARAM.to_string(), value: value.clone() })?;
+        self.settings.lock().unwrap().model = model.to_string();
+        if let Some((_, last)) = self.values.lock().unwrap().iter_mut().find(|(name, _)| *name == MODEL_PARAM) {
+            *last = value;
+        }
+        Ok(())
+    }
+}
+
+// Camera control parameters
+pub struct ControlParams {
+    // Parameter name prefix, e.g. camera. or front.camera.
+    prefix: String,
+    // Last applied value per control
+    values: Mutex<Vec<(String, i64)>>,
+}
+
+impl ControlParams {
+    // Declare one integer parameter per control with its current value.
+    // Cameras with a namespace get it as prefix (front/camera -> front.camera.exposure).
+    pub fn declare(node: &Node, namespace: &str, controls: &[(String, i64)]) -> anyhow::Result<Self> {
+        let prefix = match namespace.trim_matches('/') {
+            "" => CAMERA_PREFIX.to_string(),
+            namespace => format!("{}.{}", namespace.replace('/', "."), CAMERA_PREFIX),
+        };
+        for (name, value) in controls {
+            node.declare_parameter(&format!("{}{}", prefix, name), &ParameterValue::Integer(*value))?;
+        }
+        Ok(Self { prefix, values: Mutex::new(controls.to_vec()) })
+    }
+
+    // Returns the controls whose parameter changed since the last call
+    pub fn changed(&self, node: &Node) -> Vec<(String, i64)> {
+        let mut values = self.values.lock().unwrap();
+        let mut changed = Vec::new();
+        for (name, value) in values.iter_mut() {
+            let param = match node.get_parameter(&format!("{}{}", self.prefix, name)) {
+                Some(param) => param,
+                None => continue,
+            };
+            if let ParameterValue::Integer(new_value) = param.value {
+                if new_value != *value {
+                    *value = new_value;
+                    changed.push((name.clone(), new_value));
+                }
+            }
+        }
+        changed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings() -> NodeSettings {
+        NodeSettings { fps: 1.0, threshold: 0.5, model: "yolov8n".to_string(), mode: "none".to_string(), verbose: false }
+    }
+
+    #[test]
+    fn rejects_unknown_models() {
+        let models = vec!["yolov8n".to_string(), "B".to_string()];
+        let mut settings = settings();
+        assert!(apply(&mut settings, MODEL_PARAM, &ParameterValue::String("bogus".to_string()), &models).is_err());
+        assert_eq!(settings.model, "yolov8n");
+        assert!(apply(&mut settings, MODEL_PARAM, &ParameterValue::String("B".to_string()), &models).is_ok());
+        assert_eq!(settings.model, "B");
+    }
+
+    #[test]
+    fn fps_has_a_whole_millisecond_period() {
+        let mut settings = settings();
+        assert!(apply(&mut settings, FPS_PARAM, &ParameterValue::Integer(5000), &[]).is_err());
+        assert!(apply(&mut settings, FPS_PARAM, &ParameterValue::Double(-1.0), &[]).is_err());
+        assert!(apply(&mut settings, FPS_PARAM, &ParameterValue::Double(MAX_FPS), &[]).is_ok());
+        assert!(apply(&mut settings, FPS_PARAM, &ParameterValue::Integer(0), &[]).is_ok());
+        assert_eq!(settings.fps, 0.0);
+    }
+}
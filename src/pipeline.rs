@@ -0,0 +1,357 @@
+//! Camera pipelines
+//!
+//! One pipeline per configured camera: its capture thread, calibration and
+//! the publishers under the camera namespace. All pipelines share the
+//! detector of the node.
+//!
+//! Detections are published as vision_msgs/Detection2DArray on `detections`
+//! and, unless disabled, as the JSON meta data string on `detect`. With
+//! --annotate the debug image is also published with the detections drawn on
+//! it on `detect/annotated`. Topic names and QoS come from the topics config.
+
+use anyhow::anyhow;
+use image::{imageops::FilterType, DynamicImage, ImageOutputFormat};
+use rclrust::{Node, Publisher};
+use rclrust_msg::builtin_interfaces::msg::Time;
+use rclrust_msg::sensor_msgs::msg::CameraInfo as CameraInfoMsg;
+use rclrust_msg::sensor_msgs::msg::CompressedImage as CompressedImageMsg;
+use rclrust_msg::std_msgs::msg::Header;
+use rclrust_msg::std_msgs::msg::String as String_;
+use rclrust_msg::vision_msgs::msg::{BoundingBox2D, Detection2D, Detection2DArray, ObjectHypothesisWithPose};
+use std::io::Cursor;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, SystemTime};
+
+use crate::annotate;
+use crate::calibration::{CameraCalibration, Undistorter};
+use crate::camera::CameraConfig;
+use crate::capture::{CaptureThread, Frame};
+use crate::detection::Detection;
+use crate::frame_source::{FrameSource, SourceState};
+use crate::params::ControlParams;
+use crate::topics::TopicsConfig;
+use crate::transform::FrameTransform;
+
+// Longest wait for a new frame on trigger requests
+const FRESH_FRAME_TIMEOUT: Duration = Duration::from_secs(2);
+
+// Optional outputs, the same for every camera
+#[derive(Debug, Clone, Copy)]
+pub struct OutputOptions {
+    // JSON detection meta data on `detect`
+    pub json: bool,
+    // Debug image with the detections drawn on `detect/annotated`
+    pub annotate: bool,
+}
+
+pub struct CameraPipeline {
+    // Camera name used in logs - the namespace or "camera"
+    name: String,
+    frame_id: String,
+    // Debug image mode: none, low, med or high
+    mode: RwLock<String>,
+    // The mode is set in the camera config - the mode parameter does not apply
+    fixed_mode: bool,
+    capture: CaptureThread,
+    // Calibration of the transformed frame, published on camera_info
+    calibration: Option<CameraCalibration>,
+    undistorter: Option<Mutex<Undistorter>>,
+    // Rotation, flips and crop applied after undistortion
+    transform: FrameTransform,
+    // Camera controls as runtime adjustable parameters (camera.exposure, camera.gain, ...)
+    control_params: ControlParams,
+    last_state: Mutex<Option<SourceState>>,
+    detect_publisher: Option<Arc<Publisher<String_>>>, // JSON detection meta data publisher (--json-output)
+    detections_publisher: Arc<Publisher<Detection2DArray>>,
+    state_publisher: Arc<Publisher<String_>>,        // camera connection state publisher
+    image_publisher: Arc<Publisher<CompressedImageMsg>>,
+    annotated_publisher: Option<Arc<Publisher<CompressedImageMsg>>>, // --annotate
+    camera_info_publisher: Arc<Publisher<CameraInfoMsg>>,
+}
+
+// ROS time of a capture time
+pub fn ros_time(time: SystemTime) -> Time {
+    let since_epoch = time
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .expect("Time went backwards");
+    Time {
+        sec: since_epoch.as_secs() as i32,
+        nanosec: since_epoch.subsec_nanos() as u32,
+    }
+}
+
+fn mode_description(mode: &str) -> &'static str {
+    match mode {
+        "none" => "No debug image",
+        "low" => "Black & white image 320x180",
+        "med" => "Color image 320x180",
+        "high" => "Color image 640x360",
+        _ => unreachable!("Mode should be either 'none', 'med', low' or 'high'"), // This case should never happen
+    }
+}
+
+// Topic name under the camera namespace - kept relative so the node namespace and remapping apply
+fn topic(namespace: &str, name: &str) -> String {
+    match namespace.trim_matches('/') {
+        "" => name.to_string(),
+        namespace => format!("{}/{}", namespace, name),
+    }
+}
+
+impl CameraPipeline {
+    // Start capturing from the source and create the camera publishers
+    pub fn new(node: &Node, config: &CameraConfig, source: Box<dyn FrameSource>, default_mode: &str, outputs: OutputOptions, topics: &TopicsConfig) -> anyhow::Result<Self> {
+        let namespace = config.namespace.as_str();
+        let name = match namespace.trim_matches('/') {
+            "" => "camera".to_string(),
+            namespace => namespace.to_string(),
+        };
+        let mode = config.mode.clone().unwrap_or_else(|| default_mode.to_string());
+        let calibration = match &config.calibration {
+            Some(path) => Some(CameraCalibration::load(path)?),
+            None => None,
+        };
+        let undistorter = match (&calibration, config.undistort) {
+            (Some(calibration), true) => Some(Mutex::new(Undistorter::new(calibration.clone()))),
+            _ => None,
+        };
+        let transform = FrameTransform::from_config(config)?;
+        let calibration = match calibration {
+            Some(calibration) => {
+                let (width, height) = config.resolution;
+                Some(transform.calibration(&calibration.scaled(width, height))?)
+            }
+            None => None,
+        };
+
+        // frames are captured continuously in the background
+        let capture = CaptureThread::spawn(source)?;
+        let control_params = ControlParams::declare(node, namespace, capture.controls())?;
+        let detect_publisher = match outputs.json {
+            true => Some(node.create_publisher::<String_>(&topic(namespace, &topics.detect.name), &topics.detect.qos_profile())?),
+            false => None,
+        };
+        // nothing is published on it while the mode is none
+        let annotated_publisher = match outputs.annotate {
+            true => Some(node.create_publisher::<CompressedImageMsg>(&topic(namespace, &topics.annotated.name), &topics.annotated.qos_profile())?),
+            false => None,
+        };
+        let pipeline = Self {
+            detect_publisher,
+            detections_publisher: node.create_publisher::<Detection2DArray>(&topic(namespace, &topics.detections.name), &topics.detections.qos_profile())?,
+            state_publisher: node.create_publisher::<String_>(&topic(namespace, &topics.camera_state.name), &topics.camera_state.qos_profile())?,
+            image_publisher: node.create_publisher::<CompressedImageMsg>(&topic(namespace, &topics.image.name), &topics.image.qos_profile())?,
+            annotated_publisher,
+            camera_info_publisher: node.create_publisher::<CameraInfoMsg>(&topic(namespace, &topics.camera_info.name), &topics.camera_info.qos_profile())?,
+            name,
+            frame_id: config.frame_id.clone(),
+            mode: RwLock::new(mode),
+            fixed_mode: config.mode.is_some(),
+            capture,
+            calibration,
+            undistorter,
+            transform,
+            control_params,
+            last_state: Mutex::new(None),
+        };
+        let mut names = vec![topic(namespace, &topics.detections.name)];
+        if pipeline.detect_publisher.is_some() {
+            names.push(topic(namespace, &topics.detect.name));
+        }
+        if pipeline.annotated_publisher.is_some() {
+            names.push(topic(namespace, &topics.annotated.name));
+        }
+        println!("{}: publishing on {} ({})", pipeline.name, names.join(", "), mode_description(&pipeline.mode()));
+        Ok(pipeline)
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn mode(&self) -> String {
+        self.mode.read().unwrap().clone()
+    }
+
+    // Apply the node debug image mode - returns false for cameras with a mode in their config
+    pub fn set_mode(&self, mode: &str) -> bool {
+        if self.fixed_mode {
+            return false;
+        }
+        *self.mode.write().unwrap() = mode.to_string();
+        true
+    }
+
+    // Apply camera control parameter changes - returns the changed controls
+    pub fn apply_control_changes(&self, node: &Node) -> Vec<(String, i64)> {
+        let changed = self.control_params.changed(node);
+        for (name, value) in &changed {
+            self.capture.set_control(name, *value);
+        }
+        changed
+    }
+
+    // Publish the camera connection state when it changes - returns the published state
+    pub fn publish_state(&self) -> Option<SourceState> {
+        let state = self.capture.state();
+        let mut last_state = self.last_state.lock().unwrap();
+        if *last_state == Some(state) {
+            return None;
+        }
+        match self.state_publisher.publish(&String_ { data: state.to_string() }) {
+            Ok(_) => {
+                *last_state = Some(state);
+                Some(state)
+            }
+            Err(e) => {
+                eprintln!("{}: Failed to publish camera state: {}", self.name, e);
+                None
+            }
+        }
+    }
+
+    // Take the newest captured frame - undistorted, rotated, flipped and cropped when configured.
+    // With `fresh` it waits for a frame captured after the call.
+    pub fn next_frame(&self, fresh: bool) -> anyhow::Result<Frame> {
+        let mut frame = if fresh { self.capture.next(FRESH_FRAME_TIMEOUT)? } else { self.capture.latest()? };
+        if let Some(undistorter) = &self.undistorter {
+            frame.image = undistorter.lock().unwrap().apply(&frame.image);
+        }
+        if !self.transform.is_identity() {
+            frame.image = self.transform.apply(frame.image)?;
+        }
+        Ok(frame)
+    }
+
+    // Header of the messages about a frame - stamped with its capture time
+    fn header(&self, frame: &Frame) -> Header {
+        Header {
+            stamp: ros_time(frame.captured_at),
+            frame_id: self.frame_id.clone(),
+            ..Default::default()
+        }
+    }
+
+    // Debug image for the camera mode - None in mode none.
+    // Resize the image to smaller size to save BW - the aspect ratio is kept
+    // so the detection boxes scale uniformly to the published image
+    fn debug_image(mode: &str, img: &DynamicImage) -> Option<DynamicImage> {
+        match mode {
+            "none" => None,
+            "low" | "med" => Some(img.resize(320, 180, FilterType::Nearest)),
+            "high" => Some(img.resize(640, 360, FilterType::Nearest)),
+            _ => unreachable!("Mode should be either 'none', 'med', low' or 'high'"), // This case should never happen
+        }
+    }
+
+    // JPEG message of a debug image - grayscale in "low" mode
+    fn compressed_image(mode: &str, img: DynamicImage, header: Header) -> anyhow::Result<CompressedImageMsg> {
+        // Convert the image to grayscale for "low" mode
+        let img = match mode {
+            "low" => DynamicImage::ImageLuma8(img.to_luma8()),
+            _ => img,
+        };
+        // Convert the resized image back to a byte vector
+        let mut data = Vec::new();
+        img.write_to(&mut Cursor::new(&mut data), ImageOutputFormat::Jpeg(80)) // use quality 80 - TODO check if can decrease/increase
+            .map_err(|e| anyhow!("Failed to write resized image to buffer: {}", e))?;
+
+        // Send MSG Topic of type: CompressedImageMsg
+        Ok(CompressedImageMsg {
+            header,
+            format: "jpeg".to_string(),  // For JPEG/MJPEG format
+            data,
+        })
+    }
+
+    // Publish the debug image for the camera mode along with its camera_info,
+    // both stamped with the frame capture time
+    pub fn publish_image(&self, frame: &Frame) -> anyhow::Result<()> {
+        let img = &frame.image;
+        let mode = self.mode();
+        let resized_img = Self::debug_image(&mode, img);
+        let (image_x, image_y) = match &resized_img {
+            Some(resized_img) => (resized_img.width(), resized_img.height()),
+            None => (img.width(), img.height()),
+        };
+
+        // ROS publisher section
+        let header = self.header(frame);
+
+        // Camera model of the published image (of the detection frame when no image is published)
+        if let Some(calibration) = &self.calibration {
+            let camera_info = calibration.camera_info(header.clone(), image_x, image_y, self.undistorter.is_some());
+            self.camera_info_publisher.publish(&camera_info)
+                .map_err(|e| anyhow!("Failed to publish camera info: {}", e))?;
+        }
+        let resized_img = match resized_img {
+            Some(resized_img) => resized_img,
+            None => return Ok(()),
+        };
+        let image_message = Self::compressed_image(&mode, resized_img, header)?;
+        self.image_publisher.publish(&image_message)
+            .map_err(|e| anyhow!("Failed to publish image: {}", e))
+    }
+
+    // Publish the debug image with the detections and their estimated distance [m]
+    // drawn on it - same size and colors as the debug image
+    pub fn publish_annotated(&self, frame: &Frame, detections: &[(&Detection, Option<f64>)]) -> anyhow::Result<()> {
+        let publisher = match &self.annotated_publisher {
+            Some(publisher) => publisher,
+            None => return Ok(()),
+        };
+        let mode = self.mode();
+        let resized_img = match Self::debug_image(&mode, &frame.image) {
+            Some(resized_img) => resized_img,
+            None => return Ok(()),
+        };
+        let scale = resized_img.width() as f32 / frame.image.width() as f32;
+        let mut annotated = resized_img.to_rgb8();
+        annotate::draw_detections(&mut annotated, detections, scale);
+        let image_message = Self::compressed_image(&mode, DynamicImage::ImageRgb8(annotated), self.header(frame))?;
+        publisher.publish(&image_message)
+            .map_err(|e| anyhow!("Failed to publish annotated image: {}", e))
+    }
+
+    // Publish the JSON detection meta data - nothing is published when --json-output is off
+    pub fn publish_detections(&self, message: &String_) -> anyhow::Result<()> {
+        match &self.detect_publisher {
+            Some(publisher) => publisher.publish(message)
+                .map_err(|e| anyhow!("Failed to publish detections: {}", e)),
+            None => Ok(()),
+        }
+    }
+
+    // Publish the detections of a frame with their estimated distance [m] as a
+    // Detection2DArray - an empty array when nothing was detected
+    pub fn publish_detection_array(&self, frame: &Frame, detections: &[(&Detection, Option<f64>)]) -> anyhow::Result<()> {
+        let header = self.header(frame);
+        let detections = detections.iter()
+            .map(|(detection, dist)| {
+                let (center_x, center_y) = detection.bbox.center();
+                let mut hypothesis = ObjectHypothesisWithPose {
+                    id: detection.label.clone(),
+                    score: detection.score as f64,
+                    ..Default::default()
+                };
+                // distance along the optical axis - NaN when the class has no distance estimate
+                hypothesis.pose.pose.position.z = dist.unwrap_or(f64::NAN);
+                let mut bbox = BoundingBox2D {
+                    size_x: detection.bbox.width() as f64,
+                    size_y: detection.bbox.height() as f64,
+                    ..Default::default()
+                };
+                bbox.center.x = center_x as f64;
+                bbox.center.y = center_y as f64;
+                Detection2D {
+                    header: header.clone(),
+                    results: vec![hypothesis],
+                    bbox,
+                    ..Default::default()
+                }
+            })
+            .collect();
+        self.detections_publisher.publish(&Detection2DArray { header, detections })
+            .map_err(|e| anyhow!("Failed to publish detection array: {}", e))
+    }
+}
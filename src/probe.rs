@@ -0,0 +1,177 @@
+//! Camera probe
+//!
+//! Lists the V4L2 devices with their driver, card name and capture modes and
+//! writes a camera config block for the devices found.
+
+use anyhow::anyhow;
+use rscam::Camera;
+use std::fs::{self, OpenOptions};
+use std::io;
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+
+use crate::camera;
+
+// struct v4l2_capability
+#[repr(C)]
+struct V4l2Capability {
+    driver: [u8; 16],
+    card: [u8; 32],
+    bus_info: [u8; 32],
+    version: u32,
+    capabilities: u32,
+    device_caps: u32,
+    reserved: [u32; 3],
+}
+
+// _IOR('V', 0, struct v4l2_capability)
+const VIDIOC_QUERYCAP: u64 = 0x8068_5600;
+const V4L2_CAP_VIDEO_CAPTURE: u32 = 0x0000_0001;
+const V4L2_CAP_DEVICE_CAPS: u32 = 0x8000_0000;
+
+// Stable device links, preferred over /dev/videoN in the written config
+const DEVICE_LINK_DIRS: [&str; 2] = ["/dev/v4l/by-id", "/dev/v4l/by-path"];
+
+// What VIDIOC_QUERYCAP reports about a device
+pub struct DeviceInfo {
+    pub path: String,
+    pub driver: String,
+    pub card: String,
+    pub bus_info: String,
+    // Driver version as major.minor.patch
+    pub version: String,
+    // The device node captures video (UVC cameras also expose a metadata node)
+    pub capture: bool,
+}
+
+fn c_string(bytes: &[u8]) -> String {
+    let end = bytes.iter().position(|b| *b == 0).unwrap_or(bytes.len());
+    String::from_utf8_lossy(&bytes[..end]).to_string()
+}
+
+// Query the driver and card name of a device
+pub fn query_device(path: &str) -> io::Result<DeviceInfo> {
+    let file = OpenOptions::new().read(true).open(path)?;
+    // SAFETY: v4l2_capability is plain data, all zeroes is a valid value
+    let mut cap: V4l2Capability = unsafe { std::mem::zeroed() };
+    // SAFETY: VIDIOC_QUERYCAP fills the v4l2_capability passed to it
+    if unsafe { libc::ioctl(file.as_raw_fd(), VIDIOC_QUERYCAP as _, &mut cap as *mut V4l2Capability) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // device_caps describes this node, capabilities the whole physical device
+    let caps = if cap.capabilities & V4L2_CAP_DEVICE_CAPS != 0 { cap.device_caps } else { cap.capabilities };
+    Ok(DeviceInfo {
+        path: path.to_string(),
+        driver: c_string(&cap.driver),
+        card: c_string(&cap.card),
+        bus_info: c_string(&cap.bus_info),
+        version: format!("{}.{}.{}", cap.version >> 16, (cap.version >> 8) & 0xff, cap.version & 0xff),
+        capture: caps & V4L2_CAP_VIDEO_CAPTURE != 0,
+    })
+}
+
+// /dev/v4l/by-id (or by-path) link of a device - keeps the config valid when
+// the device number changes
+fn stable_path(path: &str) -> String {
+    let target = match fs::canonicalize(path) {
+        Ok(target) => target,
+        Err(_) => return path.to_string(),
+    };
+    for dir in DEVICE_LINK_DIRS.iter() {
+        let mut links: Vec<_> = match fs::read_dir(dir) {
+            Ok(entries) => entries.filter_map(|entry| entry.ok()).map(|entry| entry.path()).collect(),
+            Err(_) => continue,
+        };
+        links.sort();
+        if let Some(link) = links.iter().find(|link| fs::canonicalize(link).ok().as_deref() == Some(target.as_path())) {
+            return link.to_string_lossy().to_string();
+        }
+    }
+    path.to_string()
+}
+
+// Camera config block for a device in the config/camera.yaml format
+fn config_block(device: &DeviceInfo, camera: &Camera, indent: &str, namespace: Option<&str>) -> Option<String> {
+    let mode = camera::suggest_mode(camera)?;
+    let mut block = format!("{}# {} ({}, {})\n", indent, device.card, device.driver, device.bus_info);
+    let mut fields = vec![format!("device: {}", stable_path(&device.path))];
+    if let Some(namespace) = namespace {
+        fields.push(format!("namespace: {}", namespace));
+        fields.push(format!("frame_id: {}_camera", namespace));
+    }
+    fields.push(format!("resolution: [{}, {}]", mode.resolution.0, mode.resolution.1));
+    fields.push(format!("interval: [{}, {}]", mode.interval.0, mode.interval.1));
+    fields.push(format!("format: {}", mode.format));
+    fields.push(format!("nbuffers: {}", mode.nbuffers));
+    for (i, field) in fields.iter().enumerate() {
+        // list entries start with "- "
+        let prefix = if namespace.is_some() && i == 0 { "- " } else if namespace.is_some() { "  " } else { "" };
+        block.push_str(&format!("{}{}{}\n", indent, prefix, field));
+    }
+    Some(block)
+}
+
+// `probe` subcommand - print every device (or the given one) and optionally
+// write a camera config for the capture devices
+pub fn run(device: Option<&str>, output: Option<&str>) -> anyhow::Result<()> {
+    let paths = match device {
+        Some(device) => vec![device.to_string()],
+        None => camera::video_devices(),
+    };
+    if paths.is_empty() {
+        return Err(anyhow!("No /dev/video* devices found"));
+    }
+
+    let mut capture_devices = Vec::new();
+    for path in paths.iter() {
+        let info = match query_device(path) {
+            Ok(info) => info,
+            Err(e) => {
+                println!("{}: can't query the device: {}", path, e);
+                continue;
+            }
+        };
+        println!("{}: {} - {} ({}) driver {}", info.path, info.driver, info.card, info.bus_info, info.version);
+        if !info.capture {
+            println!("  no video capture (metadata or output node)");
+            continue;
+        }
+        let link = stable_path(path);
+        if link != *path {
+            println!("  link: {}", link);
+        }
+        match Camera::new(path) {
+            Ok(camera) => {
+                print!("{}", camera::list_modes(&camera));
+                capture_devices.push((info, camera));
+            }
+            Err(e) => println!("  can't open the device: {}", e),
+        }
+    }
+
+    let output = match output {
+        Some(output) => output,
+        None => return Ok(()),
+    };
+    let blocks: Vec<String> = match &capture_devices[..] {
+        [] => return Err(anyhow!("No capture device found - nothing written to {}", output)),
+        [(info, camera)] => config_block(info, camera, "", None).into_iter().collect(),
+        devices => devices.iter().enumerate()
+            .filter_map(|(i, (info, camera))| config_block(info, camera, "  ", Some(&format!("camera{}", i))))
+            .collect(),
+    };
+    if blocks.is_empty() {
+        return Err(anyhow!("No capture device supports a usable format - nothing written to {}", output));
+    }
+    let mut config = "# Written by the probe subcommand - load with --camera-config\n".to_string();
+    if capture_devices.len() > 1 {
+        config.push_str("cameras:\n");
+    }
+    config.push_str(&blocks.join(""));
+    if Path::new(output).exists() {
+        println!("Overwriting {}", output);
+    }
+    fs::write(output, config).map_err(|e| anyhow!("Can't write camera config {}: {}", output, e))?;
+    println!("Camera config written to {}", output);
+    Ok(())
+}
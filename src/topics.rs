@@ -0,0 +1,172 @@
+//! Topic names and QoS
+//!
+//! Names and QoS profiles of the published topics - load with --topics-config.
+//! Names stay relative, so the node namespace, the camera namespace and
+//! remapping (--ros-args -r detect:=...) apply.
+
+use anyhow::anyhow;
+use rclrust::qos::QoSProfile;
+use serde::Deserialize;
+use std::fs;
+
+// QoS profile of a topic
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Qos {
+    // Best effort, keep last 5 - camera images
+    SensorData,
+    // Reliable, keep last 10 - detections and state
+    Reliable,
+    // Default profile, best effort delivery
+    BestEffort,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct TopicConfig {
+    pub name: String,
+    // QoS profile (omit to keep the default QoS of the topic - filled in on load)
+    #[serde(default)]
+    pub qos: Option<Qos>,
+    // History depth (omit to keep the depth of the QoS profile)
+    #[serde(default)]
+    pub depth: Option<usize>,
+}
+
+impl TopicConfig {
+    fn new(name: &str, qos: Qos) -> Self {
+        Self { name: name.to_string(), qos: Some(qos), depth: None }
+    }
+
+    pub fn qos_profile(&self) -> QoSProfile {
+        let profile = match self.qos {
+            Some(Qos::SensorData) => QoSProfile::sensor_data(),
+            Some(Qos::Reliable) | None => QoSProfile::default().reliable(),
+            Some(Qos::BestEffort) => QoSProfile::default().best_effort(),
+        };
+        match self.depth {
+            Some(depth) => profile.keep_last(depth),
+            None => profile,
+        }
+    }
+}
+
+// Topics missing in the file keep their default name and QoS
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct TopicsConfig {
+    // JSON detection meta data (--json-output)
+    pub detect: TopicConfig,
+    // vision_msgs/Detection2DArray
+    pub detections: TopicConfig,
+    // Debug image (--mode)
+    pub image: TopicConfig,
+    // Debug image with the detections drawn (--annotate)
+    pub annotated: TopicConfig,
+    pub camera_info: TopicConfig,
+    // Camera connection state
+    pub camera_state: TopicConfig,
+    // Pipeline failures - not under a camera namespace
+    pub errors: TopicConfig,
+}
+
+impl Default for TopicsConfig {
+    fn default() -> Self {
+        Self {
+            detect: TopicConfig::new("detect", Qos::Reliable),
+            detections: TopicConfig::new("detections", Qos::Reliable),
+            image: TopicConfig::new("Compressed_camera_image", Qos::SensorData),
+            annotated: TopicConfig::new("detect/annotated", Qos::SensorData),
+            camera_info: TopicConfig::new("camera_info", Qos::SensorData),
+            camera_state: TopicConfig::new("camera/state", Qos::Reliable),
+            errors: TopicConfig::new("detect/errors", Qos::Reliable),
+        }
+    }
+}
+
+impl TopicsConfig {
+    pub fn load(path: &str) -> anyhow::Result<Self> {
+        let text = fs::read_to_string(path).map_err(|e| anyhow!("Can't read topics config {}: {}", path, e))?;
+        let config = Self::parse(&text).map_err(|e| anyhow!("Can't parse topics config {}: {}", path, e))?;
+        config.validate().map_err(|e| anyhow!("Topics config {}: {}", path, e))?;
+        Ok(config)
+    }
+
+    fn parse(text: &str) -> Result<Self, serde_yaml::Error> {
+        let mut config: TopicsConfig = serde_yaml::from_str(text)?;
+        // topics given without qos keep their default one
+        let defaults = TopicsConfig::default();
+        for (topic, (_, default)) in config.topics_mut().iter_mut().zip(defaults.topics().iter()) {
+            topic.qos = topic.qos.or(default.qos);
+        }
+        Ok(config)
+    }
+
+    fn topics(&self) -> [(&'static str, &TopicConfig); 7] {
+        [
+            ("detect", &self.detect),
+            ("detections", &self.detections),
+            ("image", &self.image),
+            ("annotated", &self.annotated),
+            ("camera_info", &self.camera_info),
+            ("camera_state", &self.camera_state),
+            ("errors", &self.errors),
+        ]
+    }
+
+    fn topics_mut(&mut self) -> [&mut TopicConfig; 7] {
+        [
+            &mut self.detect,
+            &mut self.detections,
+            &mut self.image,
+            &mut self.annotated,
+            &mut self.camera_info,
+            &mut self.camera_state,
+            &mut self.errors,
+        ]
+    }
+
+    // Names must be relative and distinct - absolute names would skip the camera namespace
+    fn validate(&self) -> anyhow::Result<()> {
+        let topics = self.topics();
+        for (i, (key, topic)) in topics.iter().enumerate() {
+            if topic.name.trim_matches('/').is_empty() {
+                return Err(anyhow!("{} has no topic name", key));
+            }
+            if topic.name.starts_with('/') || topic.name.starts_with('~') {
+                return Err(anyhow!("{} topic name should be relative - got '{}'", key, topic.name));
+            }
+            if topic.depth == Some(0) {
+                return Err(anyhow!("{} depth should be at least 1", key));
+            }
+            if let Some((other, _)) = topics[..i].iter().find(|(_, other)| other.name == topic.name) {
+                return Err(anyhow!("{} and {} use the same topic name '{}'", other, key, topic.name));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn missing_qos_keeps_the_topic_default() {
+        let config = TopicsConfig::parse("image:\n  name: camera/image\nerrors:\n  name: errors\n  qos: best_effort\n").unwrap();
+        assert_eq!(config.image.name, "camera/image");
+        assert_eq!(config.image.qos, Some(Qos::SensorData));
+        assert_eq!(config.errors.qos, Some(Qos::BestEffort));
+        assert_eq!(config.detect.qos, Some(Qos::Reliable));
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn rejects_duplicate_and_absolute_names() {
+        let config = TopicsConfig::parse("detect:\n  name: detections\n").unwrap();
+        assert!(config.validate().is_err());
+        let config = TopicsConfig::parse("detect:\n  name: /detect\n").unwrap();
+        assert!(config.validate().is_err());
+        assert!(TopicsConfig::parse("detect:\n  name: detect\n  qos: fast\n").is_err());
+    }
+}
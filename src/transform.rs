@@ -0,0 +1,285 @@
+//! Frame orientation and crop
+//!
+//! Rotation, flips and crop applied to every frame right after capture (and
+//! undistortion). The calibration is mapped the same way, so camera_info and
+//! the detection boxes match the published image.
+
+use anyhow::anyhow;
+use image::DynamicImage;
+use nalgebra::{Matrix3, Vector3};
+use serde::Deserialize;
+
+use crate::calibration::{CameraCalibration, YamlMatrix};
+use crate::camera::CameraConfig;
+
+// Crop rectangle in the rotated and flipped frame
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct Crop {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+// Elementary steps - 180 and 270 degrees are done as repeated 90 degree rotations
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Step {
+    // Clockwise: pixel (u, v) moves to (height - 1 - v, u)
+    Rotate90,
+    FlipHorizontal,
+    FlipVertical,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct FrameTransform {
+    // Clockwise rotation in degrees: 0, 90, 180 or 270
+    rotate: u32,
+    hflip: bool,
+    vflip: bool,
+    crop: Option<Crop>,
+}
+
+// Parse a crop rectangle given as X,Y,WIDTH,HEIGHT
+pub fn parse_crop(value: &str) -> Result<Crop, String> {
+    let error = || format!("Crop should be X,Y,WIDTH,HEIGHT - got '{}'", value);
+    let values: Vec<u32> = value.split(',')
+        .map(|v| v.trim().parse::<u32>())
+        .collect::<Result<_, _>>()
+        .map_err(|_| error())?;
+    match values[..] {
+        [x, y, width, height] if width > 0 && height > 0 => Ok(Crop { x, y, width, height }),
+        _ => Err(error()),
+    }
+}
+
+impl FrameTransform {
+    pub fn from_config(config: &CameraConfig) -> anyhow::Result<Self> {
+        if ![0, 90, 180, 270].contains(&config.rotate) {
+            return Err(anyhow!("rotate should be 0, 90, 180 or 270 - got {}", config.rotate));
+        }
+        let transform = Self { rotate: config.rotate, hflip: config.hflip, vflip: config.vflip, crop: config.crop };
+        // a crop outside the frame fails here instead of on every frame
+        let (width, height) = transform.oriented_size(config.resolution);
+        transform.crop_in(width, height)?;
+        Ok(transform)
+    }
+
+    // Frame size after rotation
+    fn oriented_size(&self, (width, height): (u32, u32)) -> (u32, u32) {
+        match self.rotate {
+            90 | 270 => (height, width),
+            _ => (width, height),
+        }
+    }
+
+    pub fn is_identity(&self) -> bool {
+        self.rotate == 0 && !self.hflip && !self.vflip && self.crop.is_none()
+    }
+
+    fn steps(&self) -> Vec<Step> {
+        let mut steps = vec![Step::Rotate90; (self.rotate / 90) as usize];
+        if self.hflip {
+            steps.push(Step::FlipHorizontal);
+        }
+        if self.vflip {
+            steps.push(Step::FlipVertical);
+        }
+        steps
+    }
+
+    // Crop rectangle clipped to the oriented frame
+    fn crop_in(&self, width: u32, height: u32) -> anyhow::Result<Option<Crop>> {
+        let crop = match self.crop {
+            Some(crop) => crop,
+            None => return Ok(None),
+        };
+        if crop.x >= width || crop.y >= height {
+            return Err(anyhow!("Crop {},{} is outside the {}x{} frame", crop.x, crop.y, width, height));
+        }
+        Ok(Some(Crop {
+            width: crop.width.min(width - crop.x),
+            height: crop.height.min(height - crop.y),
+            ..crop
+        }))
+    }
+
+    pub fn apply(&self, img: DynamicImage) -> anyhow::Result<DynamicImage> {
+        let mut img = match self.rotate {
+            90 => img.rotate90(),
+            180 => img.rotate180(),
+            270 => img.rotate270(),
+            _ => img,
+        };
+        if self.hflip {
+            img = img.fliph();
+        }
+        if self.vflip {
+            img = img.flipv();
+        }
+        if let Some(crop) = self.crop_in(img.width(), img.height())? {
+            img = img.crop_imm(crop.x, crop.y, crop.width, crop.height);
+        }
+        Ok(img)
+    }
+
+    // Calibration of the transformed frame - `calibration` describes the captured frame
+    pub fn calibration(&self, calibration: &CameraCalibration) -> anyhow::Result<CameraCalibration> {
+        let matrix3 = |m: &YamlMatrix| Matrix3::from_row_slice(&m.data);
+        let mut k = matrix3(&calibration.camera_matrix);
+        let mut r = matrix3(&calibration.rectification_matrix);
+        // projection matrix P = [p | p_t]
+        let projection = &calibration.projection_matrix.data;
+        let mut p = Matrix3::from_fn(|i, j| projection[i * 4 + j]);
+        let mut p_t = Vector3::new(projection[3], projection[7], projection[11]);
+        let mut d = calibration.distortion_coefficients.data.clone();
+        let (mut width, mut height) = (calibration.image_width, calibration.image_height);
+
+        for step in self.steps() {
+            // pixel mapping (a), camera axes mapping (m) and the tangential distortion (p1, p2)
+            let (a, m) = match step {
+                Step::Rotate90 => (
+                    Matrix3::new(0.0, -1.0, (height - 1) as f64, 1.0, 0.0, 0.0, 0.0, 0.0, 1.0),
+                    Matrix3::new(0.0, -1.0, 0.0, 1.0, 0.0, 0.0, 0.0, 0.0, 1.0),
+                ),
+                Step::FlipHorizontal => (
+                    Matrix3::new(-1.0, 0.0, (width - 1) as f64, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0),
+                    Matrix3::new(-1.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0),
+                ),
+                Step::FlipVertical => (
+                    Matrix3::new(1.0, 0.0, 0.0, 0.0, -1.0, (height - 1) as f64, 0.0, 0.0, 1.0),
+                    Matrix3::new(1.0, 0.0, 0.0, 0.0, -1.0, 0.0, 0.0, 0.0, 1.0),
+                ),
+            };
+            k = a * k * m.transpose();
+            p = a * p * m.transpose();
+            p_t = a * p_t;
+            r = m * r * m.transpose();
+            if d.len() >= 4 {
+                let (p1, p2) = (d[2], d[3]);
+                let (p1, p2) = match step {
+                    Step::Rotate90 => (p2, -p1),
+                    Step::FlipHorizontal => (p1, -p2),
+                    Step::FlipVertical => (-p1, p2),
+                };
+                d[2] = p1;
+                d[3] = p2;
+            }
+            if step == Step::Rotate90 {
+                std::mem::swap(&mut width, &mut height);
+            }
+        }
+        if let Some(crop) = self.crop_in(width, height)? {
+            let shift = Matrix3::new(1.0, 0.0, -(crop.x as f64), 0.0, 1.0, -(crop.y as f64), 0.0, 0.0, 1.0);
+            k = shift * k;
+            p = shift * p;
+            p_t = shift * p_t;
+            width = crop.width;
+            height = crop.height;
+        }
+
+        let rows = |m: &Matrix3<f64>| (0..3).flat_map(|i| (0..3).map(move |j| m[(i, j)])).collect::<Vec<_>>();
+        let projection = (0..3).flat_map(|i| (0..4).map(move |j| if j == 3 { p_t[i] } else { p[(i, j)] })).collect();
+        Ok(CameraCalibration {
+            image_width: width,
+            image_height: height,
+            camera_matrix: YamlMatrix::new(3, 3, rows(&k)),
+            distortion_coefficients: YamlMatrix::new(1, d.len(), d),
+            rectification_matrix: YamlMatrix::new(3, 3, rows(&r)),
+            projection_matrix: YamlMatrix::new(3, 4, projection),
+            ..calibration.clone()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{GrayImage, Luma};
+
+    const SIZE: (u32, u32) = (16, 10);
+    // Marked pixel of the captured frame
+    const MARKER: (u32, u32) = (3, 2);
+
+    type Point = (f64, f64, f64);
+
+    fn calibration() -> CameraCalibration {
+        CameraCalibration::new("test", SIZE, (10.0, 12.0, 7.0, 4.0), [0.0; 5])
+    }
+
+    fn transform(rotate: u32, hflip: bool, vflip: bool, crop: Option<Crop>) -> FrameTransform {
+        FrameTransform { rotate, hflip, vflip, crop }
+    }
+
+    // Pixel of a camera point
+    fn project(calibration: &CameraCalibration, (x, y, z): Point) -> (f64, f64) {
+        let k = &calibration.camera_matrix.data;
+        (k[0] * x / z + k[1] * y / z + k[2], k[4] * y / z + k[5])
+    }
+
+    // Position of the marker in the transformed frame
+    fn transformed_marker(transform: &FrameTransform) -> (f64, f64) {
+        let mut img = GrayImage::new(SIZE.0, SIZE.1);
+        img.put_pixel(MARKER.0, MARKER.1, Luma([255]));
+        let img = transform.apply(DynamicImage::ImageLuma8(img)).unwrap().to_luma8();
+        let (x, y, _) = img.enumerate_pixels().find(|(_, _, pixel)| pixel[0] == 255).unwrap();
+        (x as f64, y as f64)
+    }
+
+    // The marker is the projection of a camera point - after the transform the point,
+    // in the axes of the transformed camera, projects to the moved marker
+    fn check(transform: FrameTransform, axes: fn(Point) -> Point) {
+        let original = calibration();
+        let point = ((MARKER.0 as f64 - 7.0) / 10.0 * 2.0, (MARKER.1 as f64 - 4.0) / 12.0 * 2.0, 2.0);
+        assert_eq!(project(&original, point), (MARKER.0 as f64, MARKER.1 as f64));
+
+        let transformed = transform.calibration(&original).unwrap();
+        let (u, v) = project(&transformed, axes(point));
+        let expected = transformed_marker(&transform);
+        assert!((u - expected.0).abs() < 1e-9 && (v - expected.1).abs() < 1e-9, "{:?}: projected ({}, {}), marker {:?}", transform, u, v, expected);
+    }
+
+    #[test]
+    fn rotations_move_the_principal_point_with_the_image() {
+        check(transform(90, false, false, None), |(x, y, z)| (-y, x, z));
+        check(transform(180, false, false, None), |(x, y, z)| (-x, -y, z));
+        check(transform(270, false, false, None), |(x, y, z)| (y, -x, z));
+    }
+
+    #[test]
+    fn flips_mirror_the_camera_axes() {
+        check(transform(0, true, false, None), |(x, y, z)| (-x, y, z));
+        check(transform(0, false, true, None), |(x, y, z)| (x, -y, z));
+        check(transform(90, true, false, None), |(x, y, z)| (y, x, z));
+    }
+
+    #[test]
+    fn crop_shifts_the_principal_point() {
+        let crop = Crop { x: 1, y: 1, width: 10, height: 8 };
+        check(transform(0, false, false, Some(crop)), |point| point);
+        let crop = Crop { x: 2, y: 1, width: 6, height: 12 };
+        check(transform(90, false, false, Some(crop)), |(x, y, z)| (-y, x, z));
+
+        let transformed = transform(0, false, false, Some(crop)).calibration(&calibration()).unwrap();
+        assert_eq!((transformed.image_width, transformed.image_height), (6, 9));
+    }
+
+    #[test]
+    fn crop_outside_the_frame_fails_at_startup() {
+        let config = |rotate: u32, crop: Crop| CameraConfig { resolution: (640, 360), rotate, crop: Some(crop), ..CameraConfig::default() };
+        let crop = Crop { x: 400, y: 0, width: 100, height: 100 };
+        assert!(FrameTransform::from_config(&config(0, crop)).is_ok());
+        // the rotated frame is 360 pixels wide
+        assert!(FrameTransform::from_config(&config(90, crop)).is_err());
+        assert!(FrameTransform::from_config(&config(0, Crop { x: 0, y: 360, width: 10, height: 10 })).is_err());
+    }
+
+    #[test]
+    fn parses_crop() {
+        assert_eq!(parse_crop("10, 20,300,200"), Ok(Crop { x: 10, y: 20, width: 300, height: 200 }));
+        assert!(parse_crop("1,2,3").is_err());
+        assert!(parse_crop("0,0,0,5").is_err());
+        assert!(parse_crop("0,0,5,0").is_err());
+        assert!(parse_crop("-1,0,5,5").is_err());
+        assert!(parse_crop("").is_err());
+    }
+}